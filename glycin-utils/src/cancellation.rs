@@ -0,0 +1,80 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Cooperative cancellation of frame requests
+//!
+//! Glycin assigns a token to frame requests and calls the loader's `Cancel`
+//! method if the request is cancelled. Loaders can check for cancellation via
+//! [`FrameRequest::cancellation`] between rows or tiles.
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use crate::{FrameRequest, LoaderError};
+
+static STATE: Mutex<State> = Mutex::new(State {
+    cancelled: BTreeSet::new(),
+    finished: None,
+});
+
+struct State {
+    /// Tokens of cancelled requests that didn't finish yet
+    cancelled: BTreeSet<u64>,
+    /// Newest token of a finished request
+    finished: Option<u64>,
+}
+
+/// Handle to check if glycin cancelled a frame request
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cancellation {
+    token: Option<u64>,
+}
+
+impl Cancellation {
+    /// Returns `true` if glycin is no longer interested in the result
+    pub fn is_cancelled(&self) -> bool {
+        let Some(token) = self.token else {
+            return false;
+        };
+
+        STATE
+            .lock()
+            .map(|state| state.cancelled.contains(&token))
+            .unwrap_or_default()
+    }
+
+    /// Returns [`LoaderError::Cancelled`] if the request was cancelled
+    pub fn check(&self) -> Result<(), LoaderError> {
+        if self.is_cancelled() {
+            Err(LoaderError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl FrameRequest {
+    /// Handle to check if this request was cancelled
+    pub fn cancellation(&self) -> Cancellation {
+        Cancellation {
+            token: self.cancel_token,
+        }
+    }
+}
+
+/// Mark request as cancelled
+pub(crate) fn cancel(token: u64) {
+    if let Ok(mut state) = STATE.lock() {
+        // Tokens are increasing, such that older tokens belong to finished requests
+        if state.finished.map_or(true, |finished| token > finished) {
+            state.cancelled.insert(token);
+        }
+    }
+}
+
+/// Forget cancellations of the request and all previous ones
+pub(crate) fn finish(token: u64) {
+    if let Ok(mut state) = STATE.lock() {
+        state.cancelled.retain(|x| *x > token);
+        state.finished = state.finished.max(Some(token));
+    }
+}
@@ -6,22 +6,77 @@ use std::time::Duration;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use zbus::zvariant::{self, DeserializeDict, Optional, SerializeDict, Type};
 
-use crate::error::DimensionTooLargerError;
+use crate::error::{DimensionLimitError, DimensionTooLargerError};
 use crate::{SafeConversion, SafeMath};
 
 #[derive(Deserialize, Serialize, Type, Debug)]
 pub struct InitRequest {
     /// Source from which the loader reads the image data
+    ///
+    /// The kind of file descriptor is given by
+    /// [`InitializationDetails::input_kind`].
     pub fd: zvariant::OwnedFd,
     pub mime_type: String,
     pub details: InitializationDetails,
 }
 
-#[derive(DeserializeDict, SerializeDict, Type, Debug, Default)]
+#[derive(DeserializeDict, SerializeDict, Type, Debug, Clone, Default)]
 #[zvariant(signature = "dict")]
 #[non_exhaustive]
 pub struct InitializationDetails {
     pub base_dir: Option<std::path::PathBuf>,
+    /// Maximum width and height of the image
+    pub max_dimensions: Option<(u32, u32)>,
+    /// Maximum number of pixels of the image
+    pub max_pixels: Option<u64>,
+    /// Return partially decoded frames for truncated or corrupt files
+    ///
+    /// Loaders that can decode a part of the image return it with
+    /// [`FrameDetails::incomplete`] and [`FrameDetails::error`] set instead of
+    /// failing.
+    pub accept_partial: Option<bool>,
+    /// Kind of the file descriptor in [`InitRequest`]
+    ///
+    /// Only set to a seekable kind if the loader config sets
+    /// `SeekableInput = true`. Defaults to [`InputKind::Stream`].
+    pub input_kind: Option<InputKind>,
+}
+
+/// Kind of the file descriptor the image data is passed as
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputKind {
+    /// Socket through which the data is streamed
+    #[default]
+    Stream,
+    /// Sealed memfd that contains the complete data
+    Memfd,
+}
+
+impl InitializationDetails {
+    /// Check dimensions against the limits requested by glycin
+    ///
+    /// Loaders should call this as soon as the dimensions are known to not
+    /// allocate memory for images that will be rejected anyway.
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), DimensionLimitError> {
+        if let Some((max_width, max_height)) = self.max_dimensions {
+            if width > max_width || height > max_height {
+                return Err(DimensionLimitError(format!(
+                    "{width}x{height} is larger than {max_width}x{max_height}"
+                )));
+            }
+        }
+
+        if let Some(max_pixels) = self.max_pixels {
+            let pixels = u64::from(width).saturating_mul(u64::from(height));
+            if pixels > max_pixels {
+                return Err(DimensionLimitError(format!(
+                    "{width}x{height} has more than {max_pixels} pixels"
+                )));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(DeserializeDict, SerializeDict, Type, Debug, Clone, Default)]
@@ -32,6 +87,14 @@ pub struct FrameRequest {
     pub scale: Option<(u32, u32)>,
     /// Instruction to only decode part of the image
     pub clip: Option<(u32, u32, u32, u32)>,
+    /// Token that is passed to `Cancel` if glycin cancels this request
+    pub cancel_token: Option<u64>,
+    /// Return coarse previews before the final frame
+    ///
+    /// Loaders that support it answer with intermediate frames that have
+    /// [`FrameDetails::incomplete`] set. The request is repeated until the
+    /// final frame is returned.
+    pub progressive: Option<bool>,
 }
 
 /// Various image metadata
@@ -113,6 +176,14 @@ pub struct FrameDetails {
     ///
     /// Only set if it can differ for the format
     pub grayscale: Option<bool>,
+    /// Frame is not completely decoded
+    ///
+    /// Set for previews of progressive frame requests, where requesting
+    /// another frame continues decoding the same frame, and for partial
+    /// results if [`InitializationDetails::accept_partial`] is set.
+    pub incomplete: Option<bool>,
+    /// Error that stopped decoding of a partial result
+    pub error: Option<String>,
 }
 
 impl Frame {
@@ -314,3 +385,35 @@ impl MemoryFormatBytes {
         self as usize
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_dimensions() {
+        let mut details = InitializationDetails::default();
+        assert!(details.check_dimensions(u32::MAX, u32::MAX).is_ok());
+
+        details.max_dimensions = Some((100, 50));
+        assert!(details.check_dimensions(100, 50).is_ok());
+        assert!(details.check_dimensions(0, 0).is_ok());
+        assert!(details.check_dimensions(101, 50).is_err());
+        assert!(details.check_dimensions(100, 51).is_err());
+        assert!(details.check_dimensions(50, 100).is_err());
+
+        details.max_dimensions = None;
+        details.max_pixels = Some(100);
+        assert!(details.check_dimensions(10, 10).is_ok());
+        assert!(details.check_dimensions(1, 100).is_ok());
+        assert!(details.check_dimensions(0, u32::MAX).is_ok());
+        assert!(details.check_dimensions(11, 10).is_err());
+        assert!(details.check_dimensions(1, 101).is_err());
+        // Must not overflow
+        assert!(details.check_dimensions(u32::MAX, u32::MAX).is_err());
+
+        details.max_dimensions = Some((10, 100));
+        assert!(details.check_dimensions(1, 100).is_ok());
+        assert!(details.check_dimensions(2, 100).is_err());
+    }
+}
@@ -11,6 +11,56 @@ pub enum RemoteError {
     UnsupportedImageFormat(String),
     ConversionTooLargerError,
     OutOfMemory(String),
+    Truncated(String),
+    LimitExceeded(String),
+    Cancelled,
+}
+
+impl RemoteError {
+    /// Category of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::ZBus(_) => ErrorKind::LoaderCrashed,
+            Self::LoadingError(_) => ErrorKind::Corrupt,
+            Self::InternalLoaderError(_) => ErrorKind::Other,
+            Self::UnsupportedImageFormat(_) => ErrorKind::Unsupported,
+            Self::ConversionTooLargerError => ErrorKind::LimitExceeded,
+            Self::OutOfMemory(_) => ErrorKind::OutOfMemory,
+            Self::Truncated(_) => ErrorKind::Truncated,
+            Self::LimitExceeded(_) => ErrorKind::LimitExceeded,
+            Self::Cancelled => ErrorKind::Cancelled,
+        }
+    }
+}
+
+/// Category of an error
+///
+/// Allows to distinguish between problems with the image file, with the
+/// system setup, and with the loader itself without having to parse error
+/// messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The image data is damaged or invalid
+    Corrupt,
+    /// The image data ended unexpectedly
+    Truncated,
+    /// The image format or a feature of it is not supported
+    Unsupported,
+    /// Not enough memory was available
+    OutOfMemory,
+    /// A limit on resources or dimensions was exceeded
+    LimitExceeded,
+    /// The sandbox could not be set up
+    SandboxFailure,
+    /// The operation was cancelled
+    Cancelled,
+    /// The loader process terminated unexpectedly
+    LoaderCrashed,
+    /// Reading the image data failed
+    Io,
+    /// Errors that don't fit into any other category
+    Other,
 }
 
 type Location = std::panic::Location<'static>;
@@ -25,6 +75,9 @@ impl From<LoaderError> for RemoteError {
             LoaderError::UnsupportedImageFormat(msg) => Self::UnsupportedImageFormat(msg),
             LoaderError::ConversionTooLargerError => Self::ConversionTooLargerError,
             err @ LoaderError::OutOfMemory { .. } => Self::OutOfMemory(err.to_string()),
+            err @ LoaderError::Truncated { .. } => Self::Truncated(err.to_string()),
+            err @ LoaderError::LimitExceeded { .. } => Self::LimitExceeded(err.to_string()),
+            LoaderError::Cancelled => Self::Cancelled,
         }
     }
 }
@@ -42,6 +95,12 @@ pub enum LoaderError {
     ConversionTooLargerError,
     #[error("{location}: Not enough memory available")]
     OutOfMemory { location: Location },
+    #[error("{location}: Image data ended unexpectedly: {err}")]
+    Truncated { err: String, location: Location },
+    #[error("{location}: Limit exceeded: {err}")]
+    LimitExceeded { err: String, location: Location },
+    #[error("Frame request was cancelled")]
+    Cancelled,
 }
 
 impl LoaderError {
@@ -53,6 +112,96 @@ impl LoaderError {
         }
     }
 
+    #[track_caller]
+    pub fn truncated(err: &impl ToString) -> Self {
+        Self::Truncated {
+            err: err.to_string(),
+            location: *Location::caller(),
+        }
+    }
+
+    #[track_caller]
+    pub fn limit_exceeded(err: &impl ToString) -> Self {
+        Self::LimitExceeded {
+            err: err.to_string(),
+            location: *Location::caller(),
+        }
+    }
+
+    /// Category of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::LoadingError { .. } => ErrorKind::Corrupt,
+            Self::InternalLoaderError { .. } => ErrorKind::Other,
+            Self::UnsupportedImageFormat(_) => ErrorKind::Unsupported,
+            Self::ConversionTooLargerError => ErrorKind::LimitExceeded,
+            Self::OutOfMemory { .. } => ErrorKind::OutOfMemory,
+            Self::Truncated { .. } => ErrorKind::Truncated,
+            Self::LimitExceeded { .. } => ErrorKind::LimitExceeded,
+            Self::Cancelled => ErrorKind::Cancelled,
+        }
+    }
+
+    /// Create a loading error with a kind derived from the error and its
+    /// sources
+    #[track_caller]
+    fn categorized(err: &(dyn std::error::Error + 'static)) -> Self {
+        let mut source = Some(err);
+
+        while let Some(err) = source {
+            // Keep the kind and location of errors from the loader itself
+            if let Some(err) = err.downcast_ref::<LoaderError>() {
+                return match err {
+                    Self::LoadingError { err, location } => Self::LoadingError {
+                        err: err.clone(),
+                        location: *location,
+                    },
+                    Self::InternalLoaderError { err, location } => Self::InternalLoaderError {
+                        err: err.clone(),
+                        location: *location,
+                    },
+                    Self::UnsupportedImageFormat(msg) => Self::UnsupportedImageFormat(msg.clone()),
+                    Self::ConversionTooLargerError => Self::ConversionTooLargerError,
+                    Self::OutOfMemory { location } => Self::OutOfMemory {
+                        location: *location,
+                    },
+                    Self::Truncated { err, location } => Self::Truncated {
+                        err: err.clone(),
+                        location: *location,
+                    },
+                    Self::LimitExceeded { err, location } => Self::LimitExceeded {
+                        err: err.clone(),
+                        location: *location,
+                    },
+                    Self::Cancelled => Self::Cancelled,
+                };
+            }
+
+            if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+                match io_err.kind() {
+                    std::io::ErrorKind::UnexpectedEof => return LoaderError::truncated(io_err),
+                    std::io::ErrorKind::OutOfMemory => return LoaderError::out_of_memory(),
+                    _ => {}
+                }
+            }
+
+            #[cfg(feature = "image-rs")]
+            match err.downcast_ref::<image::ImageError>() {
+                Some(img_err @ image::ImageError::Limits(_)) => {
+                    return LoaderError::limit_exceeded(img_err)
+                }
+                Some(img_err @ image::ImageError::Unsupported(_)) => {
+                    return LoaderError::UnsupportedImageFormat(img_err.to_string())
+                }
+                _ => {}
+            }
+
+            source = err.source();
+        }
+
+        LoaderError::loading(err)
+    }
+
     #[track_caller]
     pub fn out_of_memory() -> Self {
         Self::OutOfMemory {
@@ -68,6 +217,13 @@ impl From<DimensionTooLargerError> for LoaderError {
     }
 }
 
+impl From<DimensionLimitError> for LoaderError {
+    #[track_caller]
+    fn from(err: DimensionLimitError) -> Self {
+        Self::limit_exceeded(&err)
+    }
+}
+
 pub trait GenericContexts<T> {
     fn loading_error(self) -> Result<T, LoaderError>;
     fn internal_error(self) -> Result<T, LoaderError>;
@@ -81,17 +237,7 @@ where
     fn loading_error(self) -> Result<T, LoaderError> {
         match self {
             Ok(x) => Ok(x),
-            Err(err) => Err(
-                if let Some(err) = ((&err) as &dyn Any).downcast_ref::<LoaderError>() {
-                    if matches!(err, LoaderError::OutOfMemory { .. }) {
-                        LoaderError::out_of_memory()
-                    } else {
-                        LoaderError::loading(err)
-                    }
-                } else {
-                    LoaderError::loading(&err)
-                },
-            ),
+            Err(err) => Err(LoaderError::categorized(&err)),
         }
     }
 
@@ -141,3 +287,41 @@ impl std::fmt::Display for DimensionTooLargerError {
 }
 
 impl std::error::Error for DimensionTooLargerError {}
+
+/// Image dimensions exceed the limits requested by glycin
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("Image dimensions exceed limit: {0}")]
+pub struct DimensionLimitError(pub(crate) String);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn categorized(err: LoaderError) -> RemoteError {
+        Err::<(), _>(err).loading_error().unwrap_err().into()
+    }
+
+    #[test]
+    fn categorized_unsupported() {
+        let err = categorized(LoaderError::UnsupportedImageFormat(String::from("AVIF")));
+
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+        assert!(matches!(err, RemoteError::UnsupportedImageFormat(msg) if msg == "AVIF"));
+    }
+
+    #[test]
+    fn categorized_cancelled() {
+        let err = categorized(LoaderError::Cancelled);
+
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+        assert!(matches!(err, RemoteError::Cancelled));
+    }
+
+    #[test]
+    fn categorized_io() {
+        let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+        let err: RemoteError = Err::<(), _>(eof).loading_error().unwrap_err().into();
+
+        assert_eq!(err.kind(), ErrorKind::Truncated);
+    }
+}
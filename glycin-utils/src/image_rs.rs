@@ -7,6 +7,11 @@ pub struct Handler {
     pub default_bit_depth: Option<u8>,
     pub supports_two_alpha_modes: bool,
     pub supports_two_grayscale_modes: bool,
+    /// Decoder writes the image sequentially such that a failed decoding
+    /// leaves a valid prefix of the image
+    pub supports_partial_results: bool,
+    /// Return the decoded part of the image if decoding fails
+    pub accept_partial: bool,
 }
 
 impl Handler {
@@ -34,6 +39,18 @@ impl Handler {
         self
     }
 
+    pub fn supports_partial_results(mut self, supports_partial_results: bool) -> Self {
+        self.supports_partial_results = supports_partial_results;
+
+        self
+    }
+
+    pub fn accept_partial(mut self, accept_partial: bool) -> Self {
+        self.accept_partial = accept_partial;
+
+        self
+    }
+
     pub fn info(&self, decoder: &mut impl image::ImageDecoder) -> ImageInfo {
         let (width, height) = decoder.dimensions();
         let mut info = ImageInfo::new(width, height);
@@ -49,13 +66,28 @@ impl Handler {
         let memory_format = MemoryFormat::from(color_type);
         let (width, height) = decoder.dimensions();
 
+        // Decoders only convert 16 bit data to native byte order after successful
+        // decoding
+        let partial = self.accept_partial
+            && self.supports_partial_results
+            && color_type.bytes_per_pixel() == color_type.channel_count();
+
         let mut memory = SharedMemory::new(decoder.total_bytes()).loading_error()?;
-        decoder.read_image(&mut memory).loading_error()?;
+        let error = match decoder.read_image(&mut memory) {
+            Ok(()) => None,
+            Err(err) if partial => Some(err.to_string()),
+            Err(err) => return Err(err).loading_error(),
+        };
         let texture = memory.into_binary_data();
 
         let mut frame = Frame::new(width, height, memory_format, texture)?;
         frame.details = details.loading_error()?;
 
+        if let Some(error) = error {
+            frame.details.incomplete = Some(true);
+            frame.details.error = Some(error);
+        }
+
         Ok(frame)
     }
 
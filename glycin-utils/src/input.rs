@@ -0,0 +1,142 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Access the image data passed by glycin
+//!
+//! Depending on the `SeekableInput` option of the loader config, glycin either
+//! streams the data through a socket or passes a sealed memfd that can be
+//! mapped into memory. [`Input::into_data`] provides the complete data in both
+//! cases.
+
+use std::io::{self, BufRead, Cursor, Read, Seek, SeekFrom};
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+use crate::InputKind;
+
+/// Image data source passed to [`LoaderImplementation::init`](crate::LoaderImplementation::init)
+#[derive(Debug)]
+pub struct Input {
+    fd: OwnedFd,
+    kind: InputKind,
+}
+
+impl Input {
+    pub(crate) fn new(fd: OwnedFd, kind: InputKind) -> Self {
+        Self { fd, kind }
+    }
+
+    /// Kind of the underlying file descriptor
+    pub fn kind(&self) -> InputKind {
+        self.kind
+    }
+
+    /// Returns whether the file descriptor supports seeking and mapping
+    pub fn is_seekable(&self) -> bool {
+        self.kind == InputKind::Memfd
+    }
+
+    /// Complete image data
+    ///
+    /// Maps seekable inputs into memory and reads streams until they are
+    /// closed.
+    pub fn into_data(self) -> io::Result<InputData> {
+        let buffer = if self.is_seekable() {
+            // Safety: Memfds are sealed by glycin
+            let mmap = unsafe { memmap::Mmap::map(&self.fd)? };
+            Buffer::Mmap(Arc::new(mmap))
+        } else {
+            let mut data = Vec::new();
+            std::fs::File::from(self.fd).read_to_end(&mut data)?;
+            Buffer::Vec(Arc::new(data))
+        };
+
+        Ok(InputData {
+            cursor: Cursor::new(buffer),
+        })
+    }
+}
+
+impl AsFd for Input {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl From<Input> for OwnedFd {
+    fn from(input: Input) -> Self {
+        input.fd
+    }
+}
+
+/// Adapter for loaders written against the stream-only API
+///
+/// Reading works for both kinds of input, but socket specific operations fail
+/// for memfds.
+impl From<Input> for UnixStream {
+    fn from(input: Input) -> Self {
+        UnixStream::from(input.fd)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Buffer {
+    Vec(Arc<Vec<u8>>),
+    Mmap(Arc<memmap::Mmap>),
+}
+
+impl AsRef<[u8]> for Buffer {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Self::Vec(data) => data,
+            Self::Mmap(mmap) => mmap,
+        }
+    }
+}
+
+/// Complete image data with its own read position
+///
+/// Cloning is cheap since the data is shared between clones.
+#[derive(Debug, Clone)]
+pub struct InputData {
+    cursor: Cursor<Buffer>,
+}
+
+impl InputData {
+    /// Length of the data in bytes
+    pub fn len(&self) -> usize {
+        self.as_ref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_ref().is_empty()
+    }
+}
+
+impl AsRef<[u8]> for InputData {
+    fn as_ref(&self) -> &[u8] {
+        self.cursor.get_ref().as_ref()
+    }
+}
+
+impl Read for InputData {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+impl BufRead for InputData {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.cursor.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.cursor.consume(amt);
+    }
+}
+
+impl Seek for InputData {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
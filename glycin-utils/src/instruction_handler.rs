@@ -1,28 +1,60 @@
 // Copyright (c) 2024 GNOME Foundation Inc.
 
+use std::any::Any;
+use std::cell::RefCell;
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::os::raw::{c_int, c_void};
 use std::os::unix::net::UnixStream;
-use std::sync::Mutex;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Condvar, Mutex, Once};
 
 use nix::libc::{c_uint, siginfo_t};
 
 use crate::dbus::*;
 use crate::error::*;
+use crate::input::Input;
+
+thread_local! {
+    /// Location of the last panic in this thread, recorded by the panic hook
+    static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Set once glycin requested the loader to terminate
+static SHUTDOWN: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+
+/// Held while a request uses the decoder
+///
+/// Requests wait for it asynchronously before locking the decoder. Otherwise,
+/// a request waiting for a running decoding would block an executor thread
+/// that is needed to dispatch `Cancel`.
+static DECODER_BUSY: async_lock::Mutex<()> = async_lock::Mutex::new(());
 
 pub struct Communication {
     _dbus_connection: zbus::Connection,
 }
 
 impl Communication {
+    /// Serve the loader until glycin requests a shutdown
     pub fn spawn(decoder: impl LoaderImplementation + 'static) {
-        futures_lite::future::block_on(async move {
-            let _connection = Communication::new(decoder).await;
-            std::future::pending::<()>().await;
-        })
+        let _communication = futures_lite::future::block_on(Communication::new(decoder));
+        Self::wait_for_shutdown();
+    }
+
+    fn wait_for_shutdown() {
+        let (lock, condvar) = &SHUTDOWN;
+        let mut shutdown = lock.lock().unwrap_or_else(|err| err.into_inner());
+        while !*shutdown {
+            shutdown = condvar
+                .wait(shutdown)
+                .unwrap_or_else(|err| err.into_inner());
+        }
     }
 
     pub async fn new(decoder: impl LoaderImplementation + 'static) -> Self {
+        Self::setup_panic_hook();
+
         let unix_stream = unsafe { UnixStream::from_raw_fd(std::io::stdin().as_raw_fd()) };
 
         let instruction_handler = Loader {
@@ -37,11 +69,39 @@ impl Communication {
             .await
             .expect("Failed to create private DBus connection");
 
+        // Decoders block the executor while they are running. Another runner is
+        // needed to receive the answers if decoders request resources from glycin.
+        let connection = dbus_connection.clone();
+        std::thread::Builder::new()
+            .name(String::from("glycin-dbus-executor"))
+            .spawn(move || {
+                futures_lite::future::block_on(async {
+                    loop {
+                        connection.executor().tick().await;
+                    }
+                })
+            })
+            .expect("Failed to spawn executor thread");
+
+        let _result = crate::resources::CONNECTION.set(dbus_connection.clone());
+
         Communication {
             _dbus_connection: dbus_connection,
         }
     }
 
+    /// Record panic locations to report them back to the host
+    fn setup_panic_hook() {
+        PANIC_HOOK.call_once(|| {
+            let default_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                let location = info.location().map(|x| x.to_string());
+                PANIC_LOCATION.with(|x| *x.borrow_mut() = location);
+                default_hook(info);
+            }));
+        });
+    }
+
     fn setup_sigsys_handler() {
         let mut mask = nix::sys::signal::SigSet::empty();
         mask.add(nix::sys::signal::Signal::SIGSYS);
@@ -98,11 +158,15 @@ impl Communication {
 pub trait LoaderImplementation: Send {
     fn init(
         &self,
-        stream: UnixStream,
+        input: Input,
         mime_type: String,
         details: InitializationDetails,
     ) -> Result<ImageInfo, LoaderError>;
     fn frame(&self, frame_request: FrameRequest) -> Result<Frame, LoaderError>;
+    /// Called before the loader terminates on request of glycin
+    ///
+    /// Loaders can use this to release resources that need cleanup.
+    fn shutdown(&self) {}
 }
 
 pub struct Loader {
@@ -113,31 +177,109 @@ pub struct Loader {
 impl Loader {
     async fn init(&self, init_request: InitRequest) -> Result<ImageInfo, RemoteError> {
         let fd = OwnedFd::from(init_request.fd);
-        let stream = UnixStream::from(fd);
-
-        let image_info = self
-            .decoder
-            .lock()
-            .map_err(|err| {
-                RemoteError::InternalLoaderError(format!(
-                    "Failed to lock decoder for init(): {err}"
-                ))
-            })?
-            .init(stream, init_request.mime_type, init_request.details)?;
+        let input = Input::new(fd, init_request.details.input_kind.unwrap_or_default());
+
+        let _busy = DECODER_BUSY.lock().await;
+        let decoder = self.decoder.lock().map_err(|err| {
+            RemoteError::InternalLoaderError(format!("Failed to lock decoder for init(): {err}"))
+        })?;
+
+        let details = init_request.details;
+        crate::progress::reset_progress();
+        let image_info = catch_panic("init", || {
+            let image_info = decoder.init(input, init_request.mime_type, details.clone())?;
+            // In case the loader didn't check the limits itself
+            details.check_dimensions(image_info.width, image_info.height)?;
+            Ok(image_info)
+        })?;
 
         Ok(image_info)
     }
 
     async fn frame(&self, frame_request: FrameRequest) -> Result<Frame, RemoteError> {
-        self.decoder
-            .lock()
-            .map_err(|err| {
-                RemoteError::InternalLoaderError(format!(
-                    "Failed to lock decoder for frame(): {err}"
-                ))
-            })?
-            .frame(frame_request)
-            .map_err(Into::into)
+        let _busy = DECODER_BUSY.lock().await;
+        let decoder = self.decoder.lock().map_err(|err| {
+            RemoteError::InternalLoaderError(format!("Failed to lock decoder for frame(): {err}"))
+        })?;
+
+        let cancel_token = frame_request.cancel_token;
+        crate::progress::reset_progress();
+        let frame = catch_panic("frame", || decoder.frame(frame_request));
+
+        if let Some(token) = cancel_token {
+            crate::cancellation::finish(token);
+        }
+
+        frame
+    }
+
+    /// Mark frame request as cancelled
+    ///
+    /// Loaders check this via [`FrameRequest::cancellation`].
+    async fn cancel(&self, cancel_token: u64) {
+        crate::cancellation::cancel(cancel_token);
+    }
+
+    /// Decoding progress as a fraction between 0 and 1
+    ///
+    /// Emitted via [`report_progress`](crate::report_progress).
+    #[zbus(signal)]
+    async fn progress(ctxt: &zbus::SignalContext<'_>, fraction: f64) -> zbus::Result<()>;
+
+    /// Let the loader clean up and terminate
+    ///
+    /// The process exits shortly after the reply, such that glycin can't rely
+    /// on receiving it.
+    async fn shutdown(&self) -> Result<(), RemoteError> {
+        let _busy = DECODER_BUSY.lock().await;
+        let result = match self.decoder.lock() {
+            Ok(decoder) => catch_panic("shutdown", || {
+                decoder.shutdown();
+                Ok(())
+            }),
+            Err(err) => Err(RemoteError::InternalLoaderError(format!(
+                "Failed to lock decoder for shutdown(): {err}"
+            ))),
+        };
+
+        let (lock, condvar) = &SHUTDOWN;
+        *lock.lock().unwrap_or_else(|err| err.into_inner()) = true;
+        condvar.notify_all();
+
+        result
+    }
+}
+
+/// Turn panics of the loader implementation into errors
+///
+/// The lock on the decoder is held outside of this function such that it
+/// doesn't get poisoned and the loader stays usable.
+fn catch_panic<T>(
+    method: &str,
+    f: impl FnOnce() -> Result<T, LoaderError>,
+) -> Result<T, RemoteError> {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result.map_err(Into::into),
+        Err(payload) => {
+            let location = PANIC_LOCATION
+                .with(|x| x.borrow_mut().take())
+                .unwrap_or_else(|| String::from("unknown location"));
+
+            Err(RemoteError::InternalLoaderError(format!(
+                "Loader panicked during {method}() at {location}: {}",
+                panic_message(payload.as_ref())
+            )))
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        String::from("Unknown panic")
     }
 }
 
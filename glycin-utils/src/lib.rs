@@ -6,12 +6,20 @@
 
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
 
+#[cfg(feature = "loader-utils")]
+pub mod cancellation;
 pub mod dbus;
 pub mod error;
 #[cfg(feature = "image-rs")]
 pub mod image_rs;
 #[cfg(feature = "loader-utils")]
+pub mod input;
+#[cfg(feature = "loader-utils")]
 pub mod instruction_handler;
+#[cfg(feature = "loader-utils")]
+pub mod progress;
+#[cfg(feature = "loader-utils")]
+pub mod resources;
 pub mod save_math;
 #[cfg(feature = "loader-utils")]
 pub mod shared_memory;
@@ -22,10 +30,18 @@ pub use std::os::unix::net::UnixStream;
 
 pub mod operations;
 
+#[cfg(feature = "loader-utils")]
+pub use cancellation::Cancellation;
 pub use dbus::*;
 pub use error::*;
 #[cfg(feature = "loader-utils")]
+pub use input::{Input, InputData};
+#[cfg(feature = "loader-utils")]
 pub use instruction_handler::*;
+#[cfg(feature = "loader-utils")]
+pub use progress::{report_progress, report_progress_rows, ProgressReader};
+#[cfg(feature = "loader-utils")]
+pub use resources::request_resource;
 pub use save_math::*;
 #[cfg(feature = "loader-utils")]
 pub use shared_memory::*;
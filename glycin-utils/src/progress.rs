@@ -0,0 +1,129 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Report decoding progress to glycin
+
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::resources::CONNECTION;
+
+/// Minimum time between two progress signals
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Last reported progress
+static LAST: Mutex<Option<(Instant, f64)>> = Mutex::new(None);
+
+/// Report progress of the current operation as a fraction between 0 and 1
+///
+/// Emits the `Progress` signal of the `org.gnome.glycin.Loader` interface.
+/// Updates are dropped if they arrive faster than every 100 ms, except for
+/// the completion. Therefore, this is cheap enough to be called for every
+/// row.
+pub fn report_progress(fraction: f64) {
+    if !fraction.is_finite() {
+        return;
+    }
+    let fraction = fraction.clamp(0., 1.);
+
+    {
+        let Ok(mut last) = LAST.lock() else {
+            return;
+        };
+
+        let now = Instant::now();
+        if let Some((instant, last_fraction)) = *last {
+            let done = fraction >= 1. && last_fraction < 1.;
+            if !done && (fraction == last_fraction || now.duration_since(instant) < MIN_INTERVAL) {
+                return;
+            }
+        }
+
+        *last = Some((now, fraction));
+    }
+
+    let Some(connection) = CONNECTION.get() else {
+        return;
+    };
+
+    let result = futures_lite::future::block_on(connection.emit_signal(
+        None::<zbus::names::BusName>,
+        "/org/gnome/glycin",
+        "org.gnome.glycin.Loader",
+        "Progress",
+        &(fraction,),
+    ));
+
+    if let Err(err) = result {
+        eprintln!("Failed to report progress: {err}");
+    }
+}
+
+/// Forget the last reported progress when a new operation starts
+///
+/// Otherwise, the first report of the operation could be dropped by the
+/// throttling.
+pub(crate) fn reset_progress() {
+    if let Ok(mut last) = LAST.lock() {
+        *last = None;
+    }
+}
+
+/// Report progress of the current operation in rows
+pub fn report_progress_rows(rows_done: u32, height: u32) {
+    if height > 0 {
+        report_progress(f64::from(rows_done) / f64::from(height));
+    }
+}
+
+/// Reader that reports how much of the input was read as progress
+///
+/// Useful for decoders that read their input while decoding.
+#[derive(Debug, Clone)]
+pub struct ProgressReader<R> {
+    inner: R,
+    len: u64,
+}
+
+impl<R: Seek> ProgressReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let position = inner.stream_position()?;
+        let len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(position))?;
+
+        Ok(Self { inner, len })
+    }
+
+    fn report(&mut self) {
+        if let Ok(position) = self.inner.stream_position() {
+            if self.len > 0 {
+                report_progress(position as f64 / self.len as f64);
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.report();
+        Ok(n)
+    }
+}
+
+impl<R: BufRead + Seek> BufRead for ProgressReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.report();
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
@@ -0,0 +1,53 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Request files that are referenced by an image
+
+use std::fs::File;
+use std::os::fd::OwnedFd;
+use std::sync::OnceLock;
+
+use zbus::zvariant;
+
+use crate::error::LoaderError;
+
+/// Connection to glycin, set once the loader is initialized
+pub(crate) static CONNECTION: OnceLock<zbus::Connection> = OnceLock::new();
+
+#[zbus::proxy(
+    interface = "org.gnome.glycin.Host",
+    default_path = "/org/gnome/glycin/host"
+)]
+trait Host {
+    async fn open_resource(&self, path: &str) -> zbus::fdo::Result<zvariant::OwnedFd>;
+}
+
+/// Request a file relative to the image from glycin
+///
+/// The path has to be relative to the image's directory and can't leave it.
+/// The file is returned as a sealed memfd. This only succeeds if the loader
+/// config sets `RequestResources = true`.
+///
+/// This blocks until glycin answered and must not be called from an async
+/// context.
+pub fn request_resource(path: &str) -> Result<File, LoaderError> {
+    let connection = CONNECTION
+        .get()
+        .ok_or_else(|| LoaderError::loading(&"Loader is not connected to glycin"))?;
+
+    futures_lite::future::block_on(async {
+        let proxy = HostProxy::builder(connection)
+            // Ununsed since P2P connection
+            .destination("org.gnome.glycin")
+            .map_err(|err| LoaderError::loading(&err))?
+            .build()
+            .await
+            .map_err(|err| LoaderError::loading(&err))?;
+
+        let fd = proxy
+            .open_resource(path)
+            .await
+            .map_err(|err| LoaderError::loading(&format!("Resource '{path}': {err}")))?;
+
+        Ok(File::from(OwnedFd::from(fd)))
+    })
+}
@@ -1,45 +1,39 @@
-use std::path::Path;
-use std::sync::OnceLock;
+use std::time::Duration;
 
-use async_global_executor::spawn_blocking;
 use gdk::gio;
 #[cfg(feature = "gobject")]
 use gio::glib;
 use gio::prelude::*;
 pub use glycin_utils::FrameDetails;
-use glycin_utils::ImageInfo;
+use glycin_utils::{ErrorKind, ImageInfo, InitializationDetails};
 
 pub use crate::config::MimeType;
 use crate::dbus::*;
-use crate::{config, Error};
-
-static IS_FLATPAKED: OnceLock<bool> = OnceLock::new();
+use crate::{config, Error, ValidationLimits};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-async fn is_flatpaked() -> bool {
-    if let Some(result) = IS_FLATPAKED.get() {
-        *result
-    } else {
-        let flatpaked = spawn_blocking(|| Path::new("/.flatpak-info").is_file()).await;
-        *IS_FLATPAKED.get_or_init(|| flatpaked)
-    }
-}
-
 #[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum SandboxMechanism {
     Bwrap,
     FlatpakSpawn,
     NotSandboxed,
+    /// Restrict filesystem access via Landlock without using namespaces
+    Landlock,
+    /// Namespace sandbox equivalent to bwrap without requiring the binary
+    Builtin,
+    /// Only apply memory limit and seccomp filters without filesystem
+    /// restrictions
+    SeccompOnly,
 }
 
 impl SandboxMechanism {
+    /// Select a mechanism that works on this system
+    ///
+    /// See [`sandbox_diagnostics()`](crate::sandbox_diagnostics) for details.
     pub async fn detect() -> Self {
-        if is_flatpaked().await {
-            Self::FlatpakSpawn
-        } else {
-            Self::Bwrap
-        }
+        crate::sandbox_diagnostics().await.recommended_mechanism
     }
 
     pub fn into_selector(self) -> SandboxSelector {
@@ -47,6 +41,9 @@ impl SandboxMechanism {
             Self::Bwrap => SandboxSelector::Bwrap,
             Self::FlatpakSpawn => SandboxSelector::FlatpakSpawn,
             Self::NotSandboxed => SandboxSelector::NotSandboxed,
+            Self::Landlock => SandboxSelector::Landlock,
+            Self::Builtin => SandboxSelector::Builtin,
+            Self::SeccompOnly => SandboxSelector::SeccompOnly,
         }
     }
 }
@@ -55,12 +52,16 @@ impl SandboxMechanism {
 #[cfg_attr(feature = "gobject", derive(gio::glib::Enum))]
 #[cfg_attr(feature = "gobject", enum_type(name = "GlySandboxSelector"))]
 #[repr(i32)]
+#[non_exhaustive]
 pub enum SandboxSelector {
     #[default]
     Auto,
     Bwrap,
     FlatpakSpawn,
     NotSandboxed,
+    Landlock,
+    Builtin,
+    SeccompOnly,
 }
 
 impl SandboxSelector {
@@ -70,6 +71,9 @@ impl SandboxSelector {
             Self::Bwrap => SandboxMechanism::Bwrap,
             Self::FlatpakSpawn => SandboxMechanism::FlatpakSpawn,
             Self::NotSandboxed => SandboxMechanism::NotSandboxed,
+            Self::Landlock => SandboxMechanism::Landlock,
+            Self::Builtin => SandboxMechanism::Builtin,
+            Self::SeccompOnly => SandboxMechanism::SeccompOnly,
         }
     }
 }
@@ -81,6 +85,12 @@ pub struct Loader {
     cancellable: gio::Cancellable,
     pub(crate) apply_transformations: bool,
     pub(crate) sandbox_mechanism: SandboxSelector,
+    timeout: Option<Duration>,
+    memory_limit: Option<u64>,
+    max_dimensions: Option<(u32, u32)>,
+    max_pixels: Option<u64>,
+    accept_partial: bool,
+    pub(crate) validation_limits: ValidationLimits,
 }
 
 impl Loader {
@@ -91,6 +101,12 @@ impl Loader {
             cancellable: gio::Cancellable::new(),
             apply_transformations: true,
             sandbox_mechanism: SandboxSelector::default(),
+            timeout: None,
+            memory_limit: None,
+            max_dimensions: None,
+            max_pixels: None,
+            accept_partial: false,
+            validation_limits: ValidationLimits::default(),
         }
     }
 
@@ -121,40 +137,160 @@ impl Loader {
         self
     }
 
+    /// Set time the loader has to load the image information or a frame
+    ///
+    /// If the loader doesn't respond in time, it is terminated and
+    /// [`Error::Timeout`] is returned. The time it takes to pass the image
+    /// data to the loader doesn't count towards the timeout. By default, only
+    /// the `Timeout` value of the loader config applies, if set.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set memory in bytes the loader can use
+    ///
+    /// Images that obviously need more memory for their texture fail with
+    /// [`Error::LimitExceeded`] before any frame is decoded. The default is
+    /// the `MemoryLimit` value of the loader config or a limit based on the
+    /// available memory.
+    pub fn memory_limit(&mut self, bytes: u64) -> &mut Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Set maximum width and height of the image
+    ///
+    /// Larger images fail with [`Error::LimitExceeded`]. The limit is passed
+    /// to the loader and checked for the image information and each frame.
+    pub fn max_dimensions(&mut self, width: u32, height: u32) -> &mut Self {
+        self.max_dimensions = Some((width, height));
+        self
+    }
+
+    /// Set maximum number of pixels of the image
+    ///
+    /// Larger images fail with [`Error::LimitExceeded`]. The limit is passed
+    /// to the loader and checked for the image information and each frame.
+    pub fn max_pixels(&mut self, pixels: u64) -> &mut Self {
+        self.max_pixels = Some(pixels);
+        self
+    }
+
+    /// Set whether to return partially decoded frames
+    ///
+    /// When enabled, loaders that can decode the valid part of truncated or
+    /// corrupt files return it instead of failing. These frames have
+    /// [`FrameDetails::incomplete`] set and the error in
+    /// [`FrameDetails::error`].
+    ///
+    /// This option is disabled by default.
+    pub fn accept_partial(&mut self, accept_partial: bool) -> &mut Self {
+        self.accept_partial = accept_partial;
+        self
+    }
+
+    /// Set limits for data returned by the loader
+    pub fn validation_limits(&mut self, validation_limits: ValidationLimits) -> &mut Self {
+        self.validation_limits = validation_limits;
+        self
+    }
+
     /// Load basic image information and enable further operations
+    ///
+    /// If multiple loaders are configured for the image's mime type, they are
+    /// tried in order of their priority until one doesn't fail with an
+    /// unsupported format or crash.
     pub async fn load<'a>(self) -> Result<Image<'a>> {
         let config = config::Config::cached().await;
 
         let gfile_worker = GFileWorker::spawn(self.file.clone(), self.cancellable.clone());
         let mime_type = Self::guess_mime_type(&gfile_worker).await?;
-        let decoder_config = config.get(&mime_type)?;
+        let mut decoder_configs = config.loaders(&mime_type)?.iter().peekable();
 
         let sandbox_mechanism = self.sandbox_mechanism.determine_sandbox_mechanism().await;
 
-        let base_dir = if decoder_config.expose_base_dir {
-            self.file.parent().and_then(|x| x.path())
-        } else {
-            None
+        let (process, info) = loop {
+            let decoder_config = decoder_configs
+                .next()
+                .ok_or_else(|| Error::UnknownImageFormat(mime_type.clone()))?;
+
+            let result = self
+                .spawn_loader(decoder_config, &mime_type, sandbox_mechanism, &gfile_worker)
+                .await;
+
+            match result {
+                Err(err)
+                    if decoder_configs.peek().is_some()
+                        && matches!(
+                            err.kind(),
+                            ErrorKind::Unsupported | ErrorKind::LoaderCrashed
+                        ) =>
+                {
+                    eprintln!(
+                        "Loader '{}' failed, trying next loader: {err}",
+                        decoder_config.exec.display()
+                    );
+                }
+                result => break result?,
+            }
         };
 
+        Ok(Image {
+            process,
+            info,
+            loader: self,
+            mime_type,
+            active_sandbox_mechanism: sandbox_mechanism,
+        })
+    }
+
+    /// Spawn a loader and load the basic image information with it
+    async fn spawn_loader<'a>(
+        &self,
+        decoder_config: &config::ImageDecoderConfig,
+        mime_type: &MimeType,
+        sandbox_mechanism: SandboxMechanism,
+        gfile_worker: &GFileWorker,
+    ) -> Result<(DecoderProcess<'a>, ImageInfo)> {
+        let mut details = self.initialization_details();
+        if decoder_config.expose_base_dir {
+            details.base_dir = self.file.parent().and_then(|x| x.path());
+        }
+
+        let timeout = self.timeout.or(decoder_config.timeout);
+
         let process = DecoderProcess::new(
-            &mime_type,
-            config,
+            mime_type,
+            decoder_config,
             sandbox_mechanism,
             &self.file,
             self.cancellable.as_ref(),
+            timeout,
+            self.memory_limit,
         )
         .await?;
 
-        let info = process.init(gfile_worker, base_dir).await?;
+        match process
+            .init(gfile_worker, details, &self.validation_limits)
+            .await
+        {
+            Ok(info) => Ok((process, info)),
+            Err(err) => {
+                // Don't keep the loader running if another loader is tried
+                let _result = process.shutdown().await;
+                Err(err)
+            }
+        }
+    }
 
-        Ok(Image {
-            process,
-            info,
-            loader: self,
-            mime_type,
-            active_sandbox_mechanism: sandbox_mechanism,
-        })
+    /// Limits the loader has to respect
+    pub(crate) fn initialization_details(&self) -> InitializationDetails {
+        let mut details = InitializationDetails::default();
+        details.max_dimensions = self.max_dimensions;
+        details.max_pixels = self.max_pixels;
+        details.accept_partial = Some(self.accept_partial);
+        details
     }
 
     async fn guess_mime_type(gfile_worker: &GFileWorker) -> Result<String> {
@@ -201,7 +337,7 @@ impl<'a> Image<'a> {
     /// function will loop to the first frame, when the last frame is reached.
     pub async fn next_frame(&self) -> Result<Frame> {
         self.process
-            .request_frame(glycin_utils::FrameRequest::default(), self)
+            .request_frame(FrameRequest::default(), self)
             .await
             .map_err(Into::into)
     }
@@ -212,11 +348,30 @@ impl<'a> Image<'a> {
     /// instructions in the `FrameRequest`.
     pub async fn specific_frame(&self, frame_request: FrameRequest) -> Result<Frame> {
         self.process
-            .request_frame(frame_request.request, self)
+            .request_frame(frame_request, self)
             .await
             .map_err(Into::into)
     }
 
+    /// Stream of the loader's progress while decoding frames
+    ///
+    /// Yields values between 0 and 1. Only loaders that support it report
+    /// progress, and only updates after this call are received. The stream
+    /// ends once the loader terminated.
+    pub fn progress_stream(&self) -> impl futures_util::Stream<Item = f64> + Send + 'static {
+        self.process.progress_stream()
+    }
+
+    /// Terminates the loader process
+    ///
+    /// Frees the loader's resources once no more frames are needed, for
+    /// example after the only frame of a still image was received. Afterwards,
+    /// requesting frames fails. Returns an error if the loader didn't exit
+    /// cleanly.
+    pub async fn release(&self) -> Result<()> {
+        self.process.shutdown().await.map_err(Into::into)
+    }
+
     /// Returns already obtained info
     pub fn info(&self) -> &ImageInfo {
         &self.info
@@ -269,7 +424,8 @@ pub struct Frame {
 #[must_use]
 /// Request information to get a specific frame
 pub struct FrameRequest {
-    request: glycin_utils::FrameRequest,
+    pub(crate) request: glycin_utils::FrameRequest,
+    pub(crate) cancellable: Option<gio::Cancellable>,
 }
 
 impl FrameRequest {
@@ -286,6 +442,29 @@ impl FrameRequest {
         self.request.clip = Some((x, y, width, height));
         self
     }
+
+    /// Set [`Cancellable`](gio::Cancellable) to cancel only this request
+    ///
+    /// Contrary to the cancellable of the [`Loader`], this keeps the loader
+    /// running such that further frames can be requested. Loaders that don't
+    /// support cancellation finish decoding the frame in the background.
+    pub fn cancellable(mut self, cancellable: impl IsA<gio::Cancellable>) -> Self {
+        self.cancellable = Some(cancellable.upcast());
+        self
+    }
+
+    /// Request coarse previews before the final frame
+    ///
+    /// Loaders that support it return intermediate frames for progressive
+    /// formats, with [`FrameDetails::incomplete`] set. Call
+    /// [`Image::specific_frame`] with the same request again until a frame
+    /// without this flag, or a partial result with [`FrameDetails::error`]
+    /// set, is returned. Other loaders directly return the final frame.
+    /// Decoding the previews increases the total decoding time.
+    pub fn progressive(mut self, progressive: bool) -> Self {
+        self.request.progressive = Some(progressive);
+        self
+    }
 }
 
 /// Returns a list of mime types for which loaders are configured
@@ -2,9 +2,11 @@ use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
+use std::time::Duration;
 
 use futures_util::StreamExt;
 use gio::glib;
+use libseccomp::ScmpSyscall;
 
 use crate::Error;
 
@@ -15,15 +17,84 @@ pub const COMPAT_VERSION: u8 = 1;
 
 static CONFIG: OnceLock<Config> = OnceLock::new();
 
+/// Environment variables passed to loaders if not configured otherwise
+const DEFAULT_PASS_ENV: &[&str] = &[
+    "LANG",
+    "LANGUAGE",
+    "LC_ALL",
+    "LC_ADDRESS",
+    "LC_COLLATE",
+    "LC_CTYPE",
+    "LC_IDENTIFICATION",
+    "LC_MEASUREMENT",
+    "LC_MESSAGES",
+    "LC_MONETARY",
+    "LC_NAME",
+    "LC_NUMERIC",
+    "LC_PAPER",
+    "LC_TELEPHONE",
+    "LC_TIME",
+];
+
 #[derive(Debug, Clone, Default)]
 pub struct Config {
-    pub image_decoders: HashMap<MimeType, ImageDecoderConfig>,
+    /// Loaders for each mime type, sorted by descending priority
+    pub image_decoders: HashMap<MimeType, Vec<ImageDecoderConfig>>,
+    /// Environment variables passed to all loaders, from the `[sandbox]` group
+    pub pass_env: Option<Vec<String>>,
+    /// Paths that are made available read-only in all sandboxes, from the
+    /// `[sandbox]` group
+    pub extra_prefixes: Vec<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ImageDecoderConfig {
     pub exec: PathBuf,
+    /// Loaders with higher priority are tried first
+    pub priority: i32,
     pub expose_base_dir: bool,
+    /// Loader can request files relative to the image
+    pub request_resources: bool,
+    /// Make the user's fonts available to the loader
+    pub expose_fonts: bool,
+    /// Loader accepts file descriptors it can seek in and map
+    pub seekable_input: bool,
+    pub seccomp_profile: SeccompProfile,
+    /// Syscalls allowed in addition to the profile
+    pub seccomp_allow: Vec<String>,
+    /// Syscalls removed from the profile
+    pub seccomp_deny: Vec<String>,
+    /// Time the loader has to answer a request
+    pub timeout: Option<Duration>,
+    /// CPU time in seconds the loader process can use
+    pub cpu_limit: Option<u64>,
+    /// Memory in bytes the loader process can use
+    pub memory_limit: Option<u64>,
+    /// Environment variables passed to the loader in addition to the global
+    /// ones
+    pub pass_env: Vec<String>,
+}
+
+/// Set of syscalls loaders are allowed to use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeccompProfile {
+    /// Suitable for loaders that use C libraries like fontconfig
+    #[default]
+    Default,
+    /// For loaders that only decode data without accessing other resources
+    Strict,
+}
+
+impl std::str::FromStr for SeccompProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "strict" => Ok(Self::Strict),
+            profile => Err(format!("Unknown seccomp profile '{profile}'")),
+        }
+    }
 }
 
 impl Config {
@@ -36,12 +107,27 @@ impl Config {
         }
     }
 
-    pub fn get(&self, mime_type: &MimeType) -> Result<&ImageDecoderConfig, Error> {
+    /// Loaders for the mime type in the order they should be tried
+    pub fn loaders(&self, mime_type: &MimeType) -> Result<&[ImageDecoderConfig], Error> {
         self.image_decoders
             .get(mime_type.as_str())
+            .map(Vec::as_slice)
+            .filter(|loaders| !loaders.is_empty())
             .ok_or_else(|| Error::UnknownImageFormat(mime_type.to_string()))
     }
 
+    /// Environment variables that are passed into the sandbox of a loader
+    pub fn pass_env(&self, loader_config: &ImageDecoderConfig) -> Vec<String> {
+        let mut vars = self
+            .pass_env
+            .clone()
+            .unwrap_or_else(|| DEFAULT_PASS_ENV.iter().map(ToString::to_string).collect());
+
+        vars.extend(loader_config.pass_env.iter().cloned());
+
+        vars
+    }
+
     async fn load() -> Self {
         let mut config = Config::default();
 
@@ -74,6 +160,18 @@ impl Config {
         keyfile.load_from_bytes(&bytes, glib::KeyFileFlags::NONE)?;
 
         for group in keyfile.groups() {
+            if group.trim() == "sandbox" {
+                if let Ok(list) = keyfile.string_list(group.trim(), "PassEnv") {
+                    config.pass_env = Some(list.iter().map(|x| x.to_string()).collect());
+                }
+                if let Ok(list) = keyfile.string_list(group.trim(), "ExtraPrefixes") {
+                    config
+                        .extra_prefixes
+                        .extend(list.iter().map(|x| PathBuf::from(x.as_str())));
+                }
+                continue;
+            }
+
             let mut elements = group.split(':');
             let kind = elements.next();
             let mime_type = elements.next();
@@ -82,15 +180,76 @@ impl Config {
                 if let Some(mime_type) = mime_type {
                     let group = group.trim();
                     if let Ok(exec) = keyfile.string(group, "Exec") {
+                        let priority = keyfile.integer(group, "Priority").unwrap_or_default();
+
                         let expose_base_dir =
                             keyfile.boolean(group, "ExposeBaseDir").unwrap_or_default();
 
+                        let request_resources = keyfile
+                            .boolean(group, "RequestResources")
+                            .unwrap_or_default();
+
+                        let expose_fonts =
+                            keyfile.boolean(group, "ExposeFonts").unwrap_or_default();
+
+                        let seekable_input =
+                            keyfile.boolean(group, "SeekableInput").unwrap_or_default();
+
+                        let seccomp_profile = match keyfile.string(group, "SeccompProfile") {
+                            Ok(profile) => profile.parse().unwrap_or_else(|err| {
+                                eprintln!("{}: {err}", path.display());
+                                SeccompProfile::default()
+                            }),
+                            Err(_) => SeccompProfile::default(),
+                        };
+
+                        let string_list = |key: &str| -> Vec<String> {
+                            keyfile
+                                .string_list(group, key)
+                                .map(|list| list.iter().map(|x| x.to_string()).collect())
+                                .unwrap_or_default()
+                        };
+
+                        // Unknown syscalls would let building the seccomp filter fail
+                        let syscall_list = |key: &str| -> Vec<String> {
+                            string_list(key)
+                                .into_iter()
+                                .filter(|name| match ScmpSyscall::from_name(name) {
+                                    Ok(_) => true,
+                                    Err(err) => {
+                                        eprintln!(
+                                            "{}: {key}: Ignoring {name:?}: {err}",
+                                            path.display()
+                                        );
+                                        false
+                                    }
+                                })
+                                .collect()
+                        };
+
+                        let timeout = keyfile.double(group, "Timeout").ok().and_then(|secs| {
+                            Duration::try_from_secs_f64(secs)
+                                .map_err(|err| eprintln!("{}: Timeout: {err}", path.display()))
+                                .ok()
+                        });
+
                         let cfg = ImageDecoderConfig {
                             exec: exec.into(),
+                            priority,
                             expose_base_dir,
+                            request_resources,
+                            expose_fonts,
+                            seekable_input,
+                            seccomp_profile,
+                            seccomp_allow: syscall_list("SeccompAllow"),
+                            seccomp_deny: syscall_list("SeccompDeny"),
+                            timeout,
+                            cpu_limit: keyfile.uint64(group, "CpuLimit").ok(),
+                            memory_limit: keyfile.uint64(group, "MemoryLimit").ok(),
+                            pass_env: string_list("PassEnv"),
                         };
 
-                        config.image_decoders.insert(mime_type.to_string(), cfg);
+                        config.add_loader(mime_type, cfg);
                     }
                 }
             }
@@ -99,6 +258,25 @@ impl Config {
         Ok(())
     }
 
+    /// Add loader for mime type, keeping the list sorted by priority
+    ///
+    /// Among loaders with the same priority, the one configured last is
+    /// tried first. A config for the same executable replaces the previous
+    /// one.
+    fn add_loader(&mut self, mime_type: &str, cfg: ImageDecoderConfig) {
+        let loaders = self
+            .image_decoders
+            .entry(mime_type.to_string())
+            .or_default();
+
+        loaders.retain(|x| x.exec != cfg.exec);
+        let index = loaders
+            .iter()
+            .position(|x| x.priority <= cfg.priority)
+            .unwrap_or(loaders.len());
+        loaders.insert(index, cfg);
+    }
+
     fn data_dirs() -> Vec<PathBuf> {
         // Force only specific data dir via env variable
         if let Some(data_dir) = std::env::var_os("GLYCIN_DATA_DIR") {
@@ -110,3 +288,74 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loader(exec: &str, priority: i32) -> ImageDecoderConfig {
+        ImageDecoderConfig {
+            exec: exec.into(),
+            priority,
+            expose_base_dir: false,
+            request_resources: false,
+            expose_fonts: false,
+            seekable_input: false,
+            seccomp_profile: SeccompProfile::default(),
+            seccomp_allow: Vec::new(),
+            seccomp_deny: Vec::new(),
+            timeout: None,
+            cpu_limit: None,
+            memory_limit: None,
+            pass_env: Vec::new(),
+        }
+    }
+
+    fn loaders(config: &Config, mime_type: &str) -> Vec<(String, i32)> {
+        config.image_decoders[mime_type]
+            .iter()
+            .map(|x| (x.exec.display().to_string(), x.priority))
+            .collect()
+    }
+
+    #[test]
+    fn add_loader_priority() {
+        let mut config = Config::default();
+        config.add_loader("image/tiff", loader("/a", 0));
+        config.add_loader("image/tiff", loader("/b", 10));
+        config.add_loader("image/tiff", loader("/c", -5));
+        config.add_loader("image/tiff", loader("/d", 0));
+        config.add_loader("image/png", loader("/a", 0));
+
+        assert_eq!(
+            loaders(&config, "image/tiff"),
+            [
+                ("/b".into(), 10),
+                ("/d".into(), 0),
+                ("/a".into(), 0),
+                ("/c".into(), -5)
+            ]
+        );
+        assert_eq!(loaders(&config, "image/png"), [("/a".into(), 0)]);
+    }
+
+    #[test]
+    fn add_loader_same_exec() {
+        let mut config = Config::default();
+        config.add_loader("image/tiff", loader("/a", 0));
+        config.add_loader("image/tiff", loader("/b", 5));
+        config.add_loader("image/tiff", loader("/a", 10));
+
+        assert_eq!(
+            loaders(&config, "image/tiff"),
+            [("/a".into(), 10), ("/b".into(), 5)]
+        );
+
+        config.add_loader("image/tiff", loader("/a", 0));
+
+        assert_eq!(
+            loaders(&config, "image/tiff"),
+            [("/b".into(), 5), ("/a".into(), 0)]
+        );
+    }
+}
@@ -2,43 +2,53 @@
 
 //! Internal DBus API
 
+use std::future::Future;
 use std::mem;
 use std::os::fd::{AsRawFd, OwnedFd, RawFd};
-use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_global_executor::{block_on, spawn_blocking};
 use futures_channel::oneshot;
-use futures_util::{future, FutureExt};
+use futures_util::{future, FutureExt, Stream, StreamExt};
 use gdk::prelude::*;
 use gio::glib;
 use glycin_utils::{
     DimensionTooLargerError, Frame, FrameRequest, ImageInfo, InitRequest, InitializationDetails,
-    MemoryFormat, RemoteError, SafeConversion, SafeMath,
+    InputKind, MemoryFormat, RemoteError, SafeConversion, SafeMath,
 };
-use nix::sys::signal;
 use zbus::zvariant;
 
 use crate::api::{self, SandboxMechanism};
+use crate::process::{self, ChildProcess};
+use crate::resources::ResourceProvider;
 use crate::sandbox::Sandbox;
-use crate::{config, icc, orientation, Error, Image};
+use crate::{config, icc, orientation, Error, Image, ValidationLimits};
 
 #[derive(Clone, Debug)]
 pub struct DecoderProcess<'a> {
-    _dbus_connection: zbus::Connection,
+    dbus_connection: zbus::Connection,
     decoding_instruction: LoaderProxy<'a>,
     mime_type: String,
+    process: ChildProcess,
+    timeout: Option<Duration>,
+    memory_limit: u64,
+    next_cancel_token: Arc<AtomicU64>,
+    seekable_input: bool,
 }
 
 impl<'a> DecoderProcess<'a> {
     pub async fn new(
         mime_type: &config::MimeType,
-        config: &config::Config,
+        loader_config: &config::ImageDecoderConfig,
         sandbox_mechanism: SandboxMechanism,
         file: &gio::File,
         cancellable: &gio::Cancellable,
+        timeout: Option<Duration>,
+        memory_limit: Option<u64>,
     ) -> Result<DecoderProcess<'a>, Error> {
-        let loader_config = config.get(mime_type)?;
+        let config = config::Config::cached().await;
 
         // UnixStream which facilitates the D-Bus connection. The stream is passed as
         // stdin to loader binaries.
@@ -50,46 +60,70 @@ impl<'a> DecoderProcess<'a> {
             .set_nonblocking(true)
             .expect("Couldn't set nonblocking");
 
-        let decoder_bin = loader_config.exec.clone();
-        let mut sandbox = Sandbox::new(sandbox_mechanism, decoder_bin, loader_stdin);
+        let mut sandbox = Sandbox::new(sandbox_mechanism, loader_config.clone(), loader_stdin);
         // Mount dir that contains the file as read only for formats like SVG
         if loader_config.expose_base_dir {
             if let Some(base_dir) = file.parent().and_then(|x| x.path()) {
                 sandbox.add_ro_bind(base_dir);
             }
         }
+        // Store paths on distributions like NixOS if not detected automatically
+        for prefix in config.extra_prefixes.iter().filter(|x| x.exists()) {
+            sandbox.add_ro_bind(prefix.clone());
+        }
+        sandbox.set_pass_env(config.pass_env(loader_config));
+        if let Some(memory_limit) = memory_limit {
+            sandbox.set_memory_limit(memory_limit);
+        }
+        let memory_limit = sandbox.memory_limit();
         let spawned_sandbox = sandbox.spawn().await?;
-        let mut subprocess = spawned_sandbox.child;
-        let command_dbg = spawned_sandbox.info.command_dbg;
+        let process = ChildProcess::new(
+            spawned_sandbox.child,
+            spawned_sandbox.info.command_dbg,
+            spawned_sandbox.info.audit_thread,
+        )?;
 
         #[cfg(feature = "tokio")]
         let unix_stream = tokio::net::UnixStream::from_std(unix_stream)?;
 
         let guid = zbus::Guid::generate();
-        let dbus_result = zbus::ConnectionBuilder::unix_stream(unix_stream)
+        let mut connection_builder = zbus::ConnectionBuilder::unix_stream(unix_stream)
             .p2p()
             .server(guid)?
-            .auth_mechanisms(&[zbus::AuthMechanism::Anonymous])
-            .build()
-            .shared();
-
-        let subprocess_id = nix::unistd::Pid::from_raw(subprocess.id().try_into().unwrap());
-
-        futures_util::select! {
-            _result = dbus_result.clone().fuse() => Ok(()),
-            _result = cancellable.future().fuse() => {
-                let _result = signal::kill(subprocess_id, signal::Signal::SIGKILL);
-                Err(glib::Error::from(gio::Cancelled).into())
-            },
-            return_status = spawn_blocking(move || subprocess.wait()).fuse() => match return_status {
-                Ok(status) => Err(Error::PrematureExit { status, cmd: command_dbg }),
-                Err(err) => Err(Error::StdIoError{ err: err.into(), info: command_dbg }),
+            .auth_mechanisms(&[zbus::AuthMechanism::Anonymous]);
+
+        // Serve files next to the image on request
+        if loader_config.request_resources {
+            if let Some(base_dir) = file.parent() {
+                connection_builder = connection_builder.serve_at(
+                    "/org/gnome/glycin/host",
+                    ResourceProvider::new(base_dir, cancellable.clone()),
+                )?;
             }
-        }?;
+        }
 
-        cancellable.connect_cancelled(move |_| {
-            let _result = signal::kill(subprocess_id, signal::Signal::SIGKILL);
-        });
+        let dbus_result = connection_builder.build().shared();
+
+        // Wait until the connection is established
+        {
+            let process_exit = process.wait().fuse();
+            futures_util::pin_mut!(process_exit);
+
+            futures_util::select! {
+                _result = dbus_result.clone().fuse() => Ok(()),
+                _result = cancellable.future().fuse() => {
+                    process.kill();
+                    Err(glib::Error::from(gio::Cancelled).into())
+                },
+                return_status = process_exit => match return_status {
+                    Ok(status) => Err(process.exit_error(status)),
+                    Err(err) => Err(err),
+                }
+            }?;
+        }
+
+        let cancelled_process = process.clone();
+        cancellable.connect_cancelled(move |_| cancelled_process.kill());
 
         let dbus_connection = dbus_result.await?;
 
@@ -101,37 +135,46 @@ impl<'a> DecoderProcess<'a> {
             .expect("Failed to create decoding instruction proxy");
 
         Ok(Self {
-            _dbus_connection: dbus_connection,
+            dbus_connection,
             decoding_instruction,
             mime_type: mime_type.to_string(),
+            process,
+            timeout,
+            memory_limit,
+            next_cancel_token: Arc::default(),
+            seekable_input: loader_config.seekable_input,
         })
     }
 
     pub async fn init(
         &self,
-        gfile_worker: GFileWorker,
-        base_dir: Option<std::path::PathBuf>,
+        gfile_worker: &GFileWorker,
+        mut details: InitializationDetails,
+        validation_limits: &ValidationLimits,
     ) -> Result<ImageInfo, Error> {
-        let (remote_reader, writer) = std::os::unix::net::UnixStream::pair()?;
-
-        gfile_worker.write_to(writer)?;
+        let (fd, input_kind) = if self.seekable_input {
+            seekable_input(gfile_worker).await?
+        } else {
+            let (remote_reader, writer) = std::os::unix::net::UnixStream::pair()?;
+            gfile_worker.write_to(writer)?;
+            (OwnedFd::from(remote_reader), InputKind::Stream)
+        };
+        details.input_kind = Some(input_kind);
 
-        let fd = zvariant::OwnedFd::from(OwnedFd::from(remote_reader));
+        let fd = zvariant::OwnedFd::from(fd);
 
         let mime_type = self.mime_type.clone();
 
-        let mut details = InitializationDetails::default();
-        details.base_dir = base_dir;
-
         let image_info = self
             .decoding_instruction
             .init(InitRequest {
                 fd,
                 mime_type,
-                details,
+                details: details.clone(),
             })
             .shared();
 
+        // Slow input like network files doesn't count towards the timeout
         let reader_error = gfile_worker.error();
         futures_util::pin_mut!(reader_error);
 
@@ -140,7 +183,14 @@ impl<'a> DecoderProcess<'a> {
             result = reader_error.fuse() => result,
         }?;
 
-        let image_info = image_info.await?;
+        let image_info = self
+            .with_timeout(async {
+                match image_info.await {
+                    Ok(image_info) => Ok(image_info),
+                    Err(err) => Err(self.process.explain_error(err.into()).await),
+                }
+            })
+            .await?;
 
         // Seal all memfds
         if let Some(exif) = &image_info.details.exif {
@@ -150,21 +200,72 @@ impl<'a> DecoderProcess<'a> {
             seal_fd(xmp)?;
         }
 
+        validation_limits.validate_info(&image_info)?;
+        details.check_dimensions(image_info.width, image_info.height)?;
+        self.check_memory_limit(&image_info)?;
+
         Ok(image_info)
     }
 
     pub async fn request_frame<'b>(
         &self,
-        frame_request: FrameRequest,
+        frame_request: api::FrameRequest,
         image: &Image<'b>,
     ) -> Result<api::Frame, Error> {
-        let mut frame = self.decoding_instruction.frame(frame_request).await?;
+        let api::FrameRequest {
+            request: mut frame_request,
+            cancellable,
+        } = frame_request;
+
+        let cancel_token = self.next_cancel_token.fetch_add(1, Ordering::Relaxed);
+        if cancellable.is_some() {
+            frame_request.cancel_token = Some(cancel_token);
+        }
+
+        let scale = frame_request.scale;
+
+        let mut frame = self
+            .with_timeout(async {
+                let frame = self.decoding_instruction.frame(frame_request).fuse();
+                let cancelled = async {
+                    match &cancellable {
+                        Some(cancellable) => cancellable.future().await,
+                        None => future::pending().await,
+                    }
+                }
+                .fuse();
+                futures_util::pin_mut!(frame);
+                futures_util::pin_mut!(cancelled);
+
+                futures_util::select! {
+                    result = frame => match result {
+                        Ok(frame) => Ok(frame),
+                        Err(err) => Err(self.process.explain_error(err.into()).await),
+                    },
+                    _result = cancelled => {
+                        // Only cancels the frame request, the loader keeps running
+                        let _result = self.decoding_instruction.cancel(cancel_token).await;
+                        Err(glib::Error::from(gio::Cancelled).into())
+                    }
+                }
+            })
+            .await?;
+
+        image
+            .loader
+            .initialization_details()
+            .check_dimensions(frame.width, frame.height)?;
 
         // Seal all constant data
         if let Some(iccp) = &frame.details.iccp {
             seal_fd(iccp)?;
         }
 
+        image
+            .loader
+            .validation_limits
+            .validate_frame(&frame, image.info(), scale)?;
+
         let raw_fd = frame.texture.as_raw_fd();
         let original_mmap = unsafe { memmap::MmapMut::map_mut(raw_fd) }?;
 
@@ -234,6 +335,89 @@ impl<'a> DecoderProcess<'a> {
             details: frame.details,
         })
     }
+
+    /// Progress signals emitted by the loader
+    ///
+    /// Only contains signals emitted after this call.
+    pub fn progress_stream(&self) -> impl Stream<Item = f64> + Send + 'static {
+        zbus::MessageStream::from(&self.dbus_connection).filter_map(|message| async move {
+            let message = message.ok()?;
+            let header = message.header();
+
+            if header.message_type() != zbus::message::Type::Signal
+                || header.interface()?.as_str() != "org.gnome.glycin.Loader"
+                || header.member()?.as_str() != "Progress"
+            {
+                return None;
+            }
+
+            let fraction = message.body().deserialize::<f64>().ok()?;
+            fraction.is_finite().then(|| fraction.clamp(0., 1.))
+        })
+    }
+
+    /// Ask the loader to terminate and wait until it exited
+    ///
+    /// The loader is killed if it doesn't exit in time.
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        if self.process.exit_status().is_none() {
+            // The loader might exit before its reply arrives
+            let _result = self.decoding_instruction.shutdown().await;
+        }
+
+        let status = self.process.wait_or_kill(process::EXIT_TIMEOUT).await?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(self.process.exit_error(status))
+        }
+    }
+
+    /// Fail early for images whose texture can't fit into the memory limit
+    ///
+    /// The memory format is only known once a frame is decoded. Therefore, the
+    /// texture size is estimated with the smallest format, [`G8`] with one byte
+    /// per pixel, such that no image that could fit is rejected.
+    ///
+    /// [`G8`]: glycin_utils::MemoryFormat::G8
+    fn check_memory_limit(&self, image_info: &ImageInfo) -> Result<(), Error> {
+        let required = u64::from(image_info.width)
+            .checked_mul(u64::from(image_info.height))
+            .unwrap_or(u64::MAX);
+
+        if required > self.memory_limit {
+            return Err(Error::LimitExceeded(format!(
+                "Image of {}x{} pixels requires at least {required} bytes, but the loader is limited to {} bytes",
+                image_info.width, image_info.height, self.memory_limit
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Terminate the loader if the request doesn't finish in time
+    async fn with_timeout<T>(
+        &self,
+        request: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let Some(duration) = self.timeout else {
+            return request.await;
+        };
+
+        let request = request.fuse();
+        let timeout = async_io::Timer::after(duration).fuse();
+        futures_util::pin_mut!(request);
+        futures_util::pin_mut!(timeout);
+
+        futures_util::select! {
+            result = request => result,
+            _instant = timeout => {
+                self.process.kill();
+                Err(Error::Timeout(duration))
+            }
+        }
+    }
 }
 
 use std::io::Write;
@@ -246,6 +430,8 @@ const BUF_SIZE: usize = u16::MAX as usize;
 trait Loader {
     async fn init(&self, init_request: InitRequest) -> Result<ImageInfo, RemoteError>;
     async fn frame(&self, frame_request: FrameRequest) -> Result<Frame, RemoteError>;
+    async fn cancel(&self, cancel_token: u64) -> zbus::Result<()>;
+    async fn shutdown(&self) -> Result<(), RemoteError>;
 }
 
 const fn gdk_memory_format(format: MemoryFormat) -> gdk::MemoryFormat {
@@ -280,14 +466,17 @@ const fn gdk_memory_format(format: MemoryFormat) -> gdk::MemoryFormat {
 
 pub struct GFileWorker {
     file: gio::File,
-    writer_send: Mutex<Option<oneshot::Sender<UnixStream>>>,
+    cancellable: gio::Cancellable,
+    writer_send: Mutex<Option<oneshot::Sender<Box<dyn Write + Send>>>>,
     first_bytes_recv: future::Shared<oneshot::Receiver<Arc<Vec<u8>>>>,
-    error_recv: future::Shared<oneshot::Receiver<Result<(), Error>>>,
+    /// Result of the last pass over the file's content
+    error_recv: Mutex<future::Shared<oneshot::Receiver<Result<(), Error>>>>,
 }
 use std::sync::Mutex;
 impl GFileWorker {
     pub fn spawn(file: gio::File, cancellable: gio::Cancellable) -> GFileWorker {
         let gfile = file.clone();
+        let gcancellable = cancellable.clone();
 
         let (error_send, error_recv) = oneshot::channel();
         let (first_bytes_send, first_bytes_recv) = oneshot::channel();
@@ -295,39 +484,49 @@ impl GFileWorker {
 
         spawn_blocking(move || {
             Self::handle_errors(error_send, move || {
-                let reader = gfile.read(Some(&cancellable))?;
+                let reader = gfile.read(Some(&gcancellable))?;
                 let mut buf = vec![0; BUF_SIZE];
 
-                let n = reader.read(&mut buf, Some(&cancellable))?;
+                let n = reader.read(&mut buf, Some(&gcancellable))?;
                 let first_bytes = Arc::new(buf[..n].to_vec());
                 first_bytes_send
                     .send(first_bytes.clone())
                     .or(Err(Error::InternalCommunicationCanceled))?;
 
-                let mut writer: UnixStream = block_on(writer_recv)?;
+                let mut writer = block_on(writer_recv)?;
 
                 writer.write_all(&first_bytes)?;
                 drop(first_bytes);
 
-                loop {
-                    let n = reader.read(&mut buf, Some(&cancellable))?;
-                    if n == 0 {
-                        break;
-                    }
-                    writer.write_all(&buf[..n])?;
-                }
-
-                Ok(())
+                Self::copy(&reader, &mut buf, &mut writer, &gcancellable)
             })
         })
         .detach();
 
         GFileWorker {
             file,
+            cancellable,
             writer_send: Mutex::new(Some(writer_send)),
             first_bytes_recv: first_bytes_recv.shared(),
-            error_recv: error_recv.shared(),
+            error_recv: Mutex::new(error_recv.shared()),
+        }
+    }
+
+    fn copy(
+        reader: &gio::FileInputStream,
+        buf: &mut [u8],
+        writer: &mut dyn Write,
+        cancellable: &gio::Cancellable,
+    ) -> Result<(), Error> {
+        loop {
+            let n = reader.read(&mut *buf, Some(cancellable))?;
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&buf[..n])?;
         }
+
+        Ok(())
     }
 
     fn handle_errors(
@@ -338,14 +537,35 @@ impl GFileWorker {
         let _result = error_send.send(result);
     }
 
-    pub fn write_to(&self, stream: UnixStream) -> Result<(), Error> {
+    /// Write the complete content of the file to `writer`
+    ///
+    /// Can be called multiple times, for example to pass the data to another
+    /// loader. Each further call reads the file again from the start.
+    pub fn write_to(&self, mut writer: impl Write + Send + 'static) -> Result<(), Error> {
         let sender = std::mem::take(&mut *self.writer_send.lock().unwrap());
 
-        sender
-            // TODO: this fails if write_to is called a second time
-            .unwrap()
-            .send(stream)
-            .or(Err(Error::InternalCommunicationCanceled))
+        if let Some(sender) = sender {
+            return sender
+                .send(Box::new(writer))
+                .or(Err(Error::InternalCommunicationCanceled));
+        }
+
+        let file = self.file.clone();
+        let cancellable = self.cancellable.clone();
+        let (error_send, error_recv) = oneshot::channel();
+
+        spawn_blocking(move || {
+            Self::handle_errors(error_send, move || {
+                let reader = file.read(Some(&cancellable))?;
+                let mut buf = vec![0; BUF_SIZE];
+                Self::copy(&reader, &mut buf, &mut writer, &cancellable)
+            })
+        })
+        .detach();
+
+        *self.error_recv.lock().unwrap() = error_recv.shared();
+
+        Ok(())
     }
 
     pub fn file(&self) -> &gio::File {
@@ -353,29 +573,50 @@ impl GFileWorker {
     }
 
     pub async fn error(&self) -> Result<(), Error> {
-        match self.error_recv.clone().await {
+        let error_recv = self.error_recv.lock().unwrap().clone();
+        match error_recv.await {
             Ok(result) => result,
             Err(_) => Ok(()),
         }
     }
 
     pub async fn head(&self) -> Result<Arc<Vec<u8>>, Error> {
+        let error_recv = self.error_recv.lock().unwrap().clone();
+
         futures_util::select!(
-            err = self.error_recv.clone() => err?,
+            err = error_recv.clone() => err?,
             _bytes = self.first_bytes_recv.clone() => Ok(()),
         )?;
 
         match self.first_bytes_recv.clone().await {
-            Err(_) => self.error_recv.clone().await?.map(|_| Default::default()),
+            Err(_) => error_recv.await?.map(|_| Default::default()),
             Ok(bytes) => Ok(bytes),
         }
     }
 }
 
-fn seal_fd(fd: impl AsRawFd) -> Result<(), memfd::Error> {
+/// Input the loader can seek in and map
+///
+/// The content is always copied into a sealed memfd. Passing the fd of a local
+/// file would allow loaders to reopen it writable via `/proc/self/fd`, and the
+/// file could change after its mime type was guessed.
+async fn seekable_input(gfile_worker: &GFileWorker) -> Result<(OwnedFd, InputKind), Error> {
+    let memfd = memfd::MemfdOptions::default()
+        .allow_sealing(true)
+        .create("glycin-input")?;
+
+    gfile_worker.write_to(memfd.as_file().try_clone()?)?;
+    gfile_worker.error().await?;
+    seal_fd(memfd.as_raw_fd())?;
+
+    Ok((OwnedFd::from(memfd.into_file()), InputKind::Memfd))
+}
+
+fn seal_fd(fd: impl AsRawFd) -> Result<(), Error> {
     let raw_fd = fd.as_raw_fd();
 
-    let mfd = memfd::Memfd::try_from_fd(raw_fd).unwrap();
+    let mfd = memfd::Memfd::try_from_fd(raw_fd)
+        .map_err(|_| Error::InvalidLoaderResponse(String::from("Data was not passed as memfd")))?;
     // In rare circumstances the sealing returns a ResourceBusy
     for i in 0.. {
         // 🦭
@@ -388,7 +629,10 @@ fn seal_fd(fd: impl AsRawFd) -> Result<(), memfd::Error> {
 
         match seal {
             Ok(_) => break,
-            Err(err) if i > 10000 => return Err(err),
+            Err(err) if i > 10000 => {
+                mem::forget(mfd);
+                return Err(err.into());
+            }
             Err(_) => {}
         }
     }
@@ -447,7 +691,8 @@ fn remove_stride_if_needed(
                 borrowed_fd,
                 libc::off_t::try_from(frame.n_bytes()?).map_err(|_| DimensionTooLargerError)?,
             )
-            .unwrap();
+            // Fails if the loader already sealed the memfd
+            .map_err(std::io::Error::from)?;
 
             // Need a new mmap with correct size
             let mmap = unsafe { memmap::MmapMut::map_mut(raw_fd) }?;
@@ -0,0 +1,111 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use async_global_executor::spawn_blocking;
+use gio::glib;
+
+use crate::{sandbox, SandboxMechanism};
+
+static DIAGNOSTICS: OnceLock<SandboxDiagnostics> = OnceLock::new();
+
+/// Sandbox capabilities of the system
+///
+/// Obtained via [`sandbox_diagnostics()`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct SandboxDiagnostics {
+    /// Running inside of a Flatpak
+    pub flatpaked: bool,
+    /// The `flatpak-spawn` binary is available
+    pub flatpak_spawn: bool,
+    /// Version reported by `bwrap --version` if the binary is available
+    pub bwrap_version: Option<String>,
+    /// bwrap can create a sandbox with seccomp filters
+    pub bwrap_usable: bool,
+    /// Unprivileged user namespaces can be created
+    pub user_namespaces: bool,
+    /// The kernel supports seccomp filters
+    pub seccomp: bool,
+    /// Landlock ABI version supported by the kernel
+    pub landlock_abi: Option<i32>,
+    /// Mechanism used with [`SandboxSelector::Auto`](crate::SandboxSelector)
+    pub recommended_mechanism: SandboxMechanism,
+}
+
+impl SandboxDiagnostics {
+    fn probe() -> Self {
+        let flatpaked = Path::new("/.flatpak-info").is_file();
+        let flatpak_spawn = glib::find_program_in_path("flatpak-spawn").is_some();
+        let seccomp = sandbox::seccomp_supported();
+        let bwrap_version = sandbox::bwrap_version();
+        // bwrap is called with seccomp filters
+        let bwrap_usable = seccomp && bwrap_version.is_some() && sandbox::bwrap_usable();
+        let user_namespaces = sandbox::builtin_usable();
+        let landlock_abi = sandbox::landlock_abi_version();
+
+        let recommended_mechanism = if flatpaked {
+            SandboxMechanism::FlatpakSpawn
+        } else if bwrap_usable {
+            SandboxMechanism::Bwrap
+        } else if seccomp && user_namespaces {
+            // The bwrap binary is not available
+            SandboxMechanism::Builtin
+        } else if seccomp && landlock_abi.is_some() {
+            // User namespaces are probably restricted on this system
+            SandboxMechanism::Landlock
+        } else {
+            // Don't run the loader completely unconfined. Without seccomp
+            // support, spawning loaders fails.
+            SandboxMechanism::SeccompOnly
+        };
+
+        Self {
+            flatpaked,
+            flatpak_spawn,
+            bwrap_version,
+            bwrap_usable,
+            user_namespaces,
+            seccomp,
+            landlock_abi,
+            recommended_mechanism,
+        }
+    }
+}
+
+impl fmt::Display for SandboxDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_none = |x: Option<String>| x.unwrap_or_else(|| String::from("-"));
+
+        writeln!(f, "flatpaked = {}", self.flatpaked)?;
+        writeln!(f, "flatpak_spawn = {}", self.flatpak_spawn)?;
+        writeln!(f, "bwrap_version = {}", or_none(self.bwrap_version.clone()))?;
+        writeln!(f, "bwrap_usable = {}", self.bwrap_usable)?;
+        writeln!(f, "user_namespaces = {}", self.user_namespaces)?;
+        writeln!(f, "seccomp = {}", self.seccomp)?;
+        writeln!(
+            f,
+            "landlock_abi = {}",
+            or_none(self.landlock_abi.map(|x| x.to_string()))
+        )?;
+        write!(
+            f,
+            "recommended_mechanism = {:?}",
+            self.recommended_mechanism
+        )
+    }
+}
+
+/// Probe which sandbox mechanisms can be used on this system
+///
+/// The result is cached after the first call.
+pub async fn sandbox_diagnostics() -> SandboxDiagnostics {
+    if let Some(diagnostics) = DIAGNOSTICS.get() {
+        diagnostics.clone()
+    } else {
+        let diagnostics = spawn_blocking(SandboxDiagnostics::probe).await;
+        DIAGNOSTICS.get_or_init(|| diagnostics).clone()
+    }
+}
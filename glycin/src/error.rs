@@ -1,10 +1,13 @@
+use std::os::unix::process::ExitStatusExt;
 use std::process::ExitStatus;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures_channel::oneshot;
-use gdk::glib;
-use glycin_utils::{DimensionTooLargerError, RemoteError};
+use gdk::{gio, glib};
+use glycin_utils::{DimensionLimitError, DimensionTooLargerError, ErrorKind, RemoteError};
 use libseccomp::error::SeccompError;
+use nix::sys::signal::Signal;
 
 use crate::MimeType;
 
@@ -26,8 +29,8 @@ pub enum Error {
     InternalCommunicationCanceled,
     #[error("Unknown image format: {0}")]
     UnknownImageFormat(MimeType),
-    #[error("Loader process exited early with status '{}'. {cmd}", .status.code().unwrap_or_default())]
-    PrematureExit { status: ExitStatus, cmd: String },
+    #[error("Loader process exited early with status '{}'. {}{0}", .0.status.code().unwrap_or_default(), .0.cmd)]
+    LoaderExited(ExitDetails),
     #[error("Conversion too large")]
     ConversionTooLargerError,
     #[error("Could not spawn `{cmd}`: {err}")]
@@ -45,8 +48,16 @@ pub enum Error {
     MemFd(Arc<memfd::Error>),
     #[error("Seccomp: {0}")]
     Seccomp(Arc<SeccompError>),
+    #[error("Landlock: {0}")]
+    Landlock(Arc<landlock::RulesetError>),
     #[error("ICC profile: {0}")]
     IccProfile(#[from] lcms2::Error),
+    #[error("Loader did not respond within {0:?}")]
+    Timeout(Duration),
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+    #[error("Invalid loader response: {0}")]
+    InvalidLoaderResponse(String),
 }
 
 impl Error {
@@ -57,6 +68,106 @@ impl Error {
             _ => None,
         }
     }
+
+    /// Category of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::RemoteError(err) => err.kind(),
+            Self::GLibError(err) if err.matches(gio::IOErrorEnum::Cancelled) => {
+                ErrorKind::Cancelled
+            }
+            Self::GLibError(_) | Self::StdIoError { .. } | Self::MemFd(_) => ErrorKind::Io,
+            Self::DbusError(_) | Self::InternalCommunicationCanceled => ErrorKind::LoaderCrashed,
+            Self::UnknownImageFormat(_) => ErrorKind::Unsupported,
+            Self::LoaderExited(details) => {
+                if details.signal.as_deref() == Some(Signal::SIGXCPU.as_str()) {
+                    ErrorKind::LimitExceeded
+                } else if details.signal.is_some() {
+                    ErrorKind::LoaderCrashed
+                } else {
+                    ErrorKind::SandboxFailure
+                }
+            }
+            Self::ConversionTooLargerError | Self::Timeout(_) | Self::LimitExceeded(_) => {
+                ErrorKind::LimitExceeded
+            }
+            Self::SpawnError { .. } | Self::Seccomp(_) | Self::Landlock(_) => {
+                ErrorKind::SandboxFailure
+            }
+            Self::TextureTooSmall { .. }
+            | Self::StrideTooSmall(_)
+            | Self::WidgthOrHeightZero(_)
+            | Self::IccProfile(_)
+            | Self::InvalidLoaderResponse(_) => ErrorKind::Corrupt,
+        }
+    }
+}
+
+/// Information about why a loader process terminated
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ExitDetails {
+    /// Exit status of the process
+    pub status: ExitStatus,
+    /// Command the process was spawned with
+    pub cmd: String,
+    /// Name of the signal that terminated the process, like `SIGSEGV`
+    pub signal: Option<String>,
+    /// Name of the syscall that was blocked by the sandbox
+    pub blocked_syscall: Option<String>,
+    /// Last lines the process wrote to stderr
+    pub stderr: Vec<String>,
+}
+
+impl ExitDetails {
+    /// Message printed by the SIGSYS handler in `glycin-utils`
+    const BLOCKED_SYSCALL_MSG: &'static str = "Blocked syscall used: ";
+
+    pub(crate) fn new(status: ExitStatus, cmd: String, stderr: Vec<String>) -> Self {
+        // bwrap and the SIGSYS handler report signals as exit code 128 + signal
+        let signal = status
+            .signal()
+            .or_else(|| {
+                status
+                    .code()
+                    .and_then(|code| code.checked_sub(128))
+                    .filter(|signal| *signal > 0)
+            })
+            .and_then(|signal| Signal::try_from(signal).ok())
+            .map(|signal| signal.as_str().to_string());
+
+        let blocked_syscall = stderr.iter().rev().find_map(|line| {
+            line.split_once(Self::BLOCKED_SYSCALL_MSG)
+                .and_then(|(_, syscall)| syscall.split_whitespace().next())
+                .map(String::from)
+        });
+
+        Self {
+            status,
+            cmd,
+            signal,
+            blocked_syscall,
+            stderr,
+        }
+    }
+}
+
+impl std::fmt::Display for ExitDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(signal) = &self.signal {
+            write!(f, "\nTerminated by signal {signal}")?;
+        }
+
+        if let Some(syscall) = &self.blocked_syscall {
+            write!(f, "\nBlocked syscall: {syscall}")?;
+        }
+
+        if !self.stderr.is_empty() {
+            write!(f, "\nLoader output:\n{}", self.stderr.join("\n"))?;
+        }
+
+        Ok(())
+    }
 }
 
 impl From<std::io::Error> for Error {
@@ -80,14 +191,83 @@ impl From<SeccompError> for Error {
     }
 }
 
+impl From<landlock::RulesetError> for Error {
+    fn from(err: landlock::RulesetError) -> Self {
+        Self::Landlock(Arc::new(err))
+    }
+}
+
 impl From<oneshot::Canceled> for Error {
     fn from(_err: oneshot::Canceled) -> Self {
         Self::InternalCommunicationCanceled
     }
 }
 
+impl From<DimensionLimitError> for Error {
+    fn from(err: DimensionLimitError) -> Self {
+        Self::LimitExceeded(err.to_string())
+    }
+}
+
 impl From<DimensionTooLargerError> for Error {
     fn from(_err: DimensionTooLargerError) -> Self {
         Self::ConversionTooLargerError
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn details(raw_status: i32, stderr: &[&str]) -> ExitDetails {
+        ExitDetails::new(
+            ExitStatus::from_raw(raw_status),
+            String::from("loader"),
+            stderr.iter().map(ToString::to_string).collect(),
+        )
+    }
+
+    #[test]
+    fn exit_details_signal() {
+        // Terminated by SIGSEGV
+        assert_eq!(details(11, &[]).signal.as_deref(), Some("SIGSEGV"));
+        // Exit code 128 + SIGKILL as reported by bwrap
+        assert_eq!(details(137 << 8, &[]).signal.as_deref(), Some("SIGKILL"));
+        // Regular exit codes
+        assert_eq!(details(1 << 8, &[]).signal, None);
+        assert_eq!(details(128 << 8, &[]).signal, None);
+        assert_eq!(details(0, &[]).signal, None);
+    }
+
+    #[test]
+    fn exit_details_blocked_syscall() {
+        let stderr = [
+            "Blocked syscall used: open",
+            "other output",
+            "glycin: Blocked syscall used: socket (41)",
+        ];
+        let exit = details(159 << 8, &stderr);
+
+        assert_eq!(exit.signal.as_deref(), Some("SIGSYS"));
+        assert_eq!(exit.blocked_syscall.as_deref(), Some("socket"));
+        assert_eq!(exit.stderr.len(), 3);
+
+        assert_eq!(details(1 << 8, &["other output"]).blocked_syscall, None);
+    }
+
+    #[test]
+    fn exit_error_kind() {
+        assert_eq!(
+            Error::LoaderExited(details(11, &[])).kind(),
+            ErrorKind::LoaderCrashed
+        );
+        assert_eq!(
+            Error::LoaderExited(details(24, &[])).kind(),
+            ErrorKind::LimitExceeded
+        );
+        assert_eq!(
+            Error::LoaderExited(details(1 << 8, &[])).kind(),
+            ErrorKind::SandboxFailure
+        );
+    }
+}
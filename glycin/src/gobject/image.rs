@@ -1,6 +1,8 @@
-use std::sync::OnceLock;
+use std::sync::{Mutex, OnceLock};
 
+use futures_util::StreamExt;
 use gio::{glib, Cancellable};
+use glib::prelude::*;
 use glib::subclass::prelude::*;
 use glycin_utils::ImageInfo;
 
@@ -12,10 +14,14 @@ static_assertions::assert_impl_all!(GlyImage: Send, Sync);
 pub mod imp {
     use super::*;
 
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, glib::Properties)]
+    #[properties(wrapper_type = super::GlyImage)]
     pub struct GlyImage {
         pub(super) image: OnceLock<Image<'static>>,
         pub(super) mime_type: OnceLock<glib::GString>,
+        /// Decoding progress reported by the loader between 0 and 1
+        #[property(get, minimum = 0.0, maximum = 1.0)]
+        pub(super) progress: Mutex<f64>,
     }
 
     #[glib::object_subclass]
@@ -24,6 +30,7 @@ pub mod imp {
         type Type = super::GlyImage;
     }
 
+    #[glib::derived_properties]
     impl ObjectImpl for GlyImage {}
 }
 
@@ -35,7 +42,21 @@ glib::wrapper! {
 impl GlyImage {
     pub(crate) fn new(image: Image<'static>) -> Self {
         let obj = glib::Object::new::<Self>();
+        let progress = image.progress_stream();
         obj.imp().image.set(image).unwrap();
+
+        // Update property in the context the image was loaded in
+        let weak_obj = obj.downgrade();
+        glib::MainContext::ref_thread_default().spawn(async move {
+            futures_util::pin_mut!(progress);
+            while let Some(fraction) = progress.next().await {
+                let Some(obj) = weak_obj.upgrade() else {
+                    break;
+                };
+                obj.set_progress(fraction);
+            }
+        });
+
         obj
     }
 
@@ -44,6 +65,8 @@ impl GlyImage {
     }
 
     pub async fn next_frame(&self) -> crate::Result<GlyFrame> {
+        // Progress refers to the current frame request
+        self.set_progress(0.);
         Ok(GlyFrame::new(self.image().next_frame().await?))
     }
 
@@ -55,6 +78,15 @@ impl GlyImage {
         self.imp().image.get().unwrap()
     }
 
+    fn set_progress(&self, fraction: f64) {
+        let mut progress = self.imp().progress.lock().unwrap();
+        if *progress != fraction {
+            *progress = fraction;
+            drop(progress);
+            self.notify_progress();
+        }
+    }
+
     pub fn mime_type(&self) -> &glib::GString {
         self.imp()
             .mime_type
@@ -33,10 +33,14 @@ mod api;
 mod config;
 mod dbus;
 mod default_formats;
+mod diagnostics;
 mod error;
 mod icc;
 mod orientation;
+mod process;
+mod resources;
 mod sandbox;
+mod validation;
 
 #[cfg(feature = "gobject")]
 pub mod gobject;
@@ -44,5 +48,7 @@ pub mod gobject;
 pub use api::*;
 pub use config::COMPAT_VERSION;
 pub use default_formats::DEFAULT_MIME_TYPES;
-pub use error::Error;
-pub use glycin_utils::{ImageInfo, ImageInfoDetails, RemoteError};
+pub use diagnostics::{sandbox_diagnostics, SandboxDiagnostics};
+pub use error::{Error, ExitDetails};
+pub use glycin_utils::{ErrorKind, ImageInfo, ImageInfoDetails, RemoteError};
+pub use validation::ValidationLimits;
@@ -0,0 +1,228 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Supervision of spawned loader processes
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, ChildStderr, ExitStatus};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use futures_channel::oneshot;
+use futures_util::future::Shared;
+use futures_util::FutureExt;
+use glycin_utils::ErrorKind;
+use nix::sys::signal;
+use nix::unistd::Pid;
+
+use crate::error::ExitDetails;
+use crate::Error;
+
+/// Number of lines from the loader's stderr that are kept for error reports
+const STDERR_TAIL_LINES: usize = 32;
+/// Lines longer than this are split
+const STDERR_MAX_LINE_LEN: u64 = 1024;
+/// Time to wait for a loader to terminate after the connection broke or a
+/// shutdown was requested
+pub const EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+type ExitResult = Result<ExitStatus, Arc<std::io::Error>>;
+
+/// Spawned loader process
+///
+/// Forwards the loader's stderr, keeps the last lines of it, and reaps the
+/// process once it terminated. The exit status is only available after the
+/// seccomp audit results of the process were reported.
+#[derive(Debug, Clone)]
+pub struct ChildProcess {
+    pid: Pid,
+    cmd: String,
+    stderr_tail: Arc<Mutex<VecDeque<String>>>,
+    exit_status: Shared<oneshot::Receiver<ExitResult>>,
+    /// Set once the process was reaped and its pid can be reused
+    reaped: Arc<Mutex<bool>>,
+}
+
+impl ChildProcess {
+    pub fn new(
+        mut child: Child,
+        cmd: String,
+        audit_thread: Option<JoinHandle<()>>,
+    ) -> Result<Self, Error> {
+        let pid = Pid::from_raw(child.id().try_into().unwrap());
+        let stderr = child.stderr.take();
+        let stderr_tail = Arc::new(Mutex::new(VecDeque::new()));
+        let (exit_send, exit_recv) = oneshot::channel();
+        let reaped = Arc::new(Mutex::new(false));
+
+        let tail = stderr_tail.clone();
+        let reaped_flag = reaped.clone();
+        std::thread::Builder::new()
+            .name(String::from("glycin-loader-stderr"))
+            .spawn(move || {
+                if let Some(stderr) = stderr {
+                    Self::read_stderr(stderr, &tail);
+                }
+
+                // Wait without reaping, such that `kill` never hits a reused pid
+                Self::wait_exited(pid);
+                let mut reaped = reaped_flag.lock().unwrap_or_else(|err| err.into_inner());
+                let status = child.wait().map_err(Arc::new);
+                *reaped = true;
+                drop(reaped);
+
+                if let Some(audit_thread) = audit_thread {
+                    let _result = audit_thread.join();
+                }
+
+                let _result = exit_send.send(status);
+            })
+            .map_err(|err| Error::SpawnError {
+                cmd: cmd.clone(),
+                err: Arc::new(err),
+            })?;
+
+        Ok(Self {
+            pid,
+            cmd,
+            stderr_tail,
+            exit_status: exit_recv.shared(),
+            reaped,
+        })
+    }
+
+    /// Kill the process unless it was already reaped
+    pub fn kill(&self) {
+        let reaped = self.reaped.lock().unwrap_or_else(|err| err.into_inner());
+        if !*reaped {
+            let _result = signal::kill(self.pid, signal::Signal::SIGKILL);
+        }
+    }
+
+    /// Block until the process terminated, leaving it waitable
+    fn wait_exited(pid: Pid) {
+        let Ok(id) = libc::id_t::try_from(pid.as_raw()) else {
+            return;
+        };
+
+        loop {
+            let mut info = std::mem::MaybeUninit::<libc::siginfo_t>::zeroed();
+            let result = unsafe {
+                libc::waitid(
+                    libc::P_PID,
+                    id,
+                    info.as_mut_ptr(),
+                    libc::WEXITED | libc::WNOWAIT,
+                )
+            };
+
+            if result == 0
+                || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted
+            {
+                break;
+            }
+        }
+    }
+
+    /// Wait until the process terminated
+    pub async fn wait(&self) -> Result<ExitStatus, Error> {
+        match self.exit_status.clone().await {
+            Ok(Ok(status)) => Ok(status),
+            Ok(Err(err)) => Err(Error::StdIoError {
+                err,
+                info: self.cmd.clone(),
+            }),
+            Err(_) => Err(Error::InternalCommunicationCanceled),
+        }
+    }
+
+    /// Exit status if the process already terminated and was reaped
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status
+            .peek()
+            .and_then(|result| result.as_ref().ok())
+            .and_then(|result| result.as_ref().ok())
+            .copied()
+    }
+
+    /// Wait for the process to terminate and kill it after the timeout
+    pub async fn wait_or_kill(&self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let exit = self.wait().fuse();
+        let timeout = async_io::Timer::after(timeout).fuse();
+        futures_util::pin_mut!(exit);
+        futures_util::pin_mut!(timeout);
+
+        futures_util::select! {
+            status = exit => return status,
+            _instant = timeout => self.kill(),
+        }
+
+        self.wait().await
+    }
+
+    /// Error describing why the process terminated
+    pub fn exit_error(&self, status: ExitStatus) -> Error {
+        let stderr = self
+            .stderr_tail
+            .lock()
+            .map(|tail| tail.iter().cloned().collect())
+            .unwrap_or_default();
+
+        Error::LoaderExited(ExitDetails::new(status, self.cmd.clone(), stderr))
+    }
+
+    /// Replace communication errors with the reason the loader terminated
+    ///
+    /// When a loader crashes, the D-Bus connection breaks before the exit
+    /// status is available. Therefore, this waits a short time for the process
+    /// to terminate.
+    pub async fn explain_error(&self, err: Error) -> Error {
+        if err.kind() != ErrorKind::LoaderCrashed {
+            return err;
+        }
+
+        let exit = self.wait().fuse();
+        let timeout = async_io::Timer::after(EXIT_TIMEOUT).fuse();
+        futures_util::pin_mut!(exit);
+        futures_util::pin_mut!(timeout);
+
+        futures_util::select! {
+            status = exit => match status {
+                Ok(status) => self.exit_error(status),
+                Err(_) => err,
+            },
+            _instant = timeout => err,
+        }
+    }
+
+    fn read_stderr(stderr: ChildStderr, tail: &Mutex<VecDeque<String>>) {
+        let mut reader = BufReader::new(stderr);
+        let mut buf = Vec::new();
+
+        loop {
+            buf.clear();
+            match reader
+                .by_ref()
+                .take(STDERR_MAX_LINE_LEN)
+                .read_until(b'\n', &mut buf)
+            {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches('\n');
+
+            // Forward output as if the process had inherited stderr
+            eprintln!("{line}");
+
+            if let Ok(mut tail) = tail.lock() {
+                if tail.len() >= STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line.to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Files referenced by images that loaders can request
+//!
+//! Instead of making the image's directory available in the sandbox, loaders
+//! request single files relative to the image. Local files are opened without
+//! leaving the image's directory, others via GIO. The files are passed to the
+//! loader as sealed memfds.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_global_executor::spawn_blocking;
+use gio::prelude::*;
+use memfd::{FileSeal, MemfdOptions};
+use zbus::fdo;
+use zbus::zvariant;
+
+/// Maximum number of resources a loader can request per image
+const MAX_REQUESTS: usize = 256;
+/// Maximum size of a single resource
+const MAX_RESOURCE_SIZE: i64 = 128 * 1024 * 1024;
+
+pub struct ResourceProvider {
+    base_dir: gio::File,
+    cancellable: gio::Cancellable,
+    n_requests: AtomicUsize,
+}
+
+impl ResourceProvider {
+    pub fn new(base_dir: gio::File, cancellable: gio::Cancellable) -> Self {
+        Self {
+            base_dir,
+            cancellable,
+            n_requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// Check that the path only descends into the base dir
+    fn relative_path(path: &str) -> fdo::Result<&Path> {
+        let relative_path = Path::new(path);
+
+        let only_descends = relative_path
+            .components()
+            .all(|x| matches!(x, Component::Normal(_) | Component::CurDir));
+
+        if path.is_empty() || !only_descends {
+            return Err(fdo::Error::AccessDenied(format!(
+                "Resource '{path}' is not inside the image's directory"
+            )));
+        }
+
+        Ok(relative_path)
+    }
+
+    /// Open a local file without leaving the base dir
+    ///
+    /// The file is opened once and read from the resulting fd, such that
+    /// symlinks swapped in after the check can't point outside of the dir.
+    fn open_beneath(base_path: &Path, relative_path: &Path) -> io::Result<File> {
+        let base_dir = File::open(base_path)?;
+        let path = CString::new(relative_path.as_os_str().as_bytes())?;
+        let flags = libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NOCTTY;
+
+        // Safety: `open_how` is a plain C struct
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = flags as u64;
+        how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                base_dir.as_raw_fd(),
+                path.as_ptr(),
+                &how as *const libc::open_how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        };
+
+        if result >= 0 {
+            let fd = RawFd::try_from(result).map_err(|err| io::Error::other(err.to_string()))?;
+            // Safety: The fd was just opened and is not owned by anything else
+            return Ok(unsafe { File::from_raw_fd(fd) });
+        }
+
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ENOSYS) {
+            return Err(err);
+        }
+
+        // Kernels without `openat2` don't follow any symlinks instead
+        let mut dir = base_dir;
+        let mut components = relative_path.components().peekable();
+        while let Some(component) = components.next() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+
+            let name = CString::new(name.as_bytes())?;
+            let flags = if components.peek().is_some() {
+                flags | libc::O_NOFOLLOW | libc::O_DIRECTORY
+            } else {
+                flags | libc::O_NOFOLLOW
+            };
+
+            let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Safety: The fd was just opened and is not owned by anything else
+            dir = unsafe { File::from_raw_fd(fd) };
+        }
+
+        Ok(dir)
+    }
+
+    /// Read a file inside the local base dir
+    fn read_local(base_path: &Path, relative_path: &Path) -> fdo::Result<Vec<u8>> {
+        let not_found = |err: io::Error| {
+            fdo::Error::FileNotFound(format!("Resource '{}': {err}", relative_path.display()))
+        };
+
+        let file = Self::open_beneath(base_path, relative_path).map_err(not_found)?;
+        let metadata = file.metadata().map_err(not_found)?;
+
+        if !metadata.is_file() {
+            return Err(fdo::Error::FileNotFound(format!(
+                "Resource '{}' is not a regular file",
+                relative_path.display()
+            )));
+        }
+
+        Self::check_size(i64::try_from(metadata.len()).unwrap_or(i64::MAX))?;
+
+        let mut data = Vec::new();
+        file.take(MAX_RESOURCE_SIZE.unsigned_abs())
+            .read_to_end(&mut data)
+            .map_err(not_found)?;
+
+        Ok(data)
+    }
+
+    /// Read a file via GIO for base dirs that are not local
+    fn read_gio(file: &gio::File, cancellable: &gio::Cancellable) -> fdo::Result<Vec<u8>> {
+        let size = file
+            .query_info(
+                gio::FILE_ATTRIBUTE_STANDARD_SIZE,
+                gio::FileQueryInfoFlags::NONE,
+                Some(cancellable),
+            )
+            .map_err(|err| fdo::Error::FileNotFound(err.to_string()))?
+            .size();
+
+        Self::check_size(size)?;
+
+        let (data, _etag) = file
+            .load_contents(Some(cancellable))
+            .map_err(|err| fdo::Error::FileNotFound(err.to_string()))?;
+
+        Ok(data.to_vec())
+    }
+
+    fn check_size(size: i64) -> fdo::Result<()> {
+        if size > MAX_RESOURCE_SIZE {
+            return Err(fdo::Error::LimitsExceeded(format!(
+                "Resource is larger than {MAX_RESOURCE_SIZE} bytes"
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn load(
+        base_dir: &gio::File,
+        path: &str,
+        cancellable: &gio::Cancellable,
+    ) -> fdo::Result<OwnedFd> {
+        let failed = |err: &dyn std::fmt::Display| fdo::Error::Failed(err.to_string());
+
+        let relative_path = Self::relative_path(path)?;
+        let data = match base_dir.path() {
+            Some(base_path) => Self::read_local(&base_path, relative_path)?,
+            None => Self::read_gio(&base_dir.resolve_relative_path(relative_path), cancellable)?,
+        };
+
+        let memfd = MemfdOptions::default()
+            .allow_sealing(true)
+            .create("glycin-resource")
+            .map_err(|err| failed(&err))?;
+
+        memfd
+            .as_file()
+            .write_all(&data)
+            .map_err(|err| failed(&err))?;
+
+        memfd
+            .add_seals(&[
+                FileSeal::SealShrink,
+                FileSeal::SealGrow,
+                FileSeal::SealWrite,
+                FileSeal::SealSeal,
+            ])
+            .map_err(|err| failed(&err))?;
+
+        Ok(OwnedFd::from(memfd.into_file()))
+    }
+}
+
+#[zbus::interface(name = "org.gnome.glycin.Host")]
+impl ResourceProvider {
+    async fn open_resource(&self, path: String) -> fdo::Result<zvariant::OwnedFd> {
+        if self.n_requests.fetch_add(1, Ordering::Relaxed) >= MAX_REQUESTS {
+            return Err(fdo::Error::LimitsExceeded(format!(
+                "Only {MAX_REQUESTS} resources can be requested"
+            )));
+        }
+
+        let base_dir = self.base_dir.clone();
+        let cancellable = self.cancellable.clone();
+
+        let fd = spawn_blocking(move || Self::load(&base_dir, &path, &cancellable)).await?;
+
+        Ok(zvariant::OwnedFd::from(fd))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Image dir `base` with links pointing inside and outside of it
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("glycin-resources-{name}-{}", std::process::id()));
+        let _result = std::fs::remove_dir_all(&dir);
+
+        let base = dir.join("base");
+        std::fs::create_dir_all(base.join("sub")).unwrap();
+        std::fs::write(base.join("sub/res.png"), b"inside").unwrap();
+        std::fs::write(dir.join("secret"), b"outside").unwrap();
+
+        symlink("../secret", base.join("link-out")).unwrap();
+        symlink(dir.join("secret"), base.join("link-abs")).unwrap();
+        symlink("..", base.join("parent")).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn relative_path() {
+        assert!(ResourceProvider::relative_path("sub/res.png").is_ok());
+        assert!(ResourceProvider::relative_path("./res.png").is_ok());
+
+        assert!(ResourceProvider::relative_path("").is_err());
+        assert!(ResourceProvider::relative_path("../secret").is_err());
+        assert!(ResourceProvider::relative_path("sub/../../secret").is_err());
+        assert!(ResourceProvider::relative_path("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn read_local() {
+        let dir = test_dir("read-local");
+        let base = dir.join("base");
+        let read = |path: &str| ResourceProvider::read_local(&base, Path::new(path));
+
+        assert_eq!(read("sub/res.png").unwrap(), b"inside");
+        assert_eq!(read("./sub/res.png").unwrap(), b"inside");
+
+        assert!(read("link-out").is_err());
+        assert!(read("link-abs").is_err());
+        assert!(read("parent/secret").is_err());
+        assert!(read("parent/base/sub/res.png").is_err());
+        // Directories
+        assert!(read("sub").is_err());
+        assert!(read("missing").is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,24 +1,43 @@
 // Copyright (c) 2024 GNOME Foundation Inc.
 
+mod audit;
+mod elf;
+mod landlock;
+mod native;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
 use std::fs::{canonicalize, DirEntry, File};
-use std::io::{self, BufRead, BufReader, Seek};
+use std::io::{self, BufRead, BufReader, Read, Seek};
 use std::os::fd::{AsRawFd, OwnedFd};
 use std::os::unix::net::UnixStream;
 use std::os::unix::process::CommandExt;
-use std::path::PathBuf;
-use std::process::{Child, Command};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
 use std::sync::Arc;
+use std::thread::JoinHandle;
 
+use async_global_executor::spawn_blocking;
+use gio::glib;
 use libseccomp::error::SeccompError;
-use libseccomp::{ScmpAction, ScmpFilterContext, ScmpSyscall};
+use libseccomp::{ScmpAction, ScmpArgCompare, ScmpCompareOp, ScmpFilterContext, ScmpSyscall};
 use memfd::{Memfd, MemfdOptions};
 use nix::sys::resource;
 
+use self::native::NativeSandbox;
+use crate::config::{ImageDecoderConfig, SeccompProfile};
 use crate::{Error, SandboxMechanism};
 
 static SYSTEM_SETUP: async_lock::Mutex<Option<Arc<io::Result<SystemSetup>>>> =
     async_lock::Mutex::new(None);
 
+/// Files needed to start a loader binary that are not part of the system dirs
+static LOADER_DEPENDENCIES: std::sync::Mutex<BTreeMap<PathBuf, Vec<PathBuf>>> =
+    std::sync::Mutex::new(BTreeMap::new());
+
+/// Dirs where each entry is a package, like on NixOS or Guix
+const STORE_DIRS: &[&str] = &["/nix/store", "/gnu/store"];
+
 const ALLOWED_SYSCALLS: &[&str] = &[
     "access",
     "arch_prctl",
@@ -126,16 +145,88 @@ const ALLOWED_SYSCALLS: &[&str] = &[
     "timerfd_settime64",
     "tgkill",
     "ugetrlimit",
-    "unshare",
     "wait4",
     "write",
 ];
 
+/// Environment variables set inside of sandboxes
+const SANDBOX_ENV: &[(&str, &str)] = &[("HOME", "/tmp-home"), ("XDG_RUNTIME_DIR", "/tmp-run")];
+
+/// Entry of the filesystem layout inside the sandbox
+#[derive(Debug, Clone)]
+enum Mount {
+    /// Read only bind mount of the host path `src` to `dest`
+    ///
+    /// Optional mounts are skipped if the path doesn't exist.
+    RoBind {
+        src: PathBuf,
+        dest: PathBuf,
+        optional: bool,
+    },
+    /// Symlink at `link` pointing to `target`
+    Symlink { target: PathBuf, link: PathBuf },
+    /// Empty tmpfs
+    Tmpfs(PathBuf),
+    /// Minimal `/dev` with devices like `/dev/null`
+    Dev(PathBuf),
+}
+
+impl Mount {
+    fn ro_bind(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self::RoBind {
+            src: path.clone(),
+            dest: path,
+            optional: false,
+        }
+    }
+
+    fn ro_bind_try(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self::RoBind {
+            src: path.clone(),
+            dest: path,
+            optional: true,
+        }
+    }
+
+    /// Optional bind mount to a different location inside the sandbox
+    fn ro_bind_try_to(src: impl Into<PathBuf>, dest: impl Into<PathBuf>) -> Self {
+        Self::RoBind {
+            src: src.into(),
+            dest: dest.into(),
+            optional: true,
+        }
+    }
+}
+
+/// Used by glibc to name anonymous memory regions
+const PR_SET_VMA: u64 = 0x53564d41;
+
+/// Flag for `close_range` to set close-on-exec instead of closing
+const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+
+/// Syscalls removed from [`ALLOWED_SYSCALLS`] for the strict profile
+const STRICT_DENIED_SYSCALLS: &[&str] = &[
+    "capget",
+    "capset",
+    "connect",
+    "creat",
+    "get_mempolicy",
+    "pivot_root",
+    "set_mempolicy",
+    "socket",
+    "socketcall",
+];
+
 pub struct Sandbox {
     sandbox_mechanism: SandboxMechanism,
+    config: ImageDecoderConfig,
     command: PathBuf,
     stdin: UnixStream,
     ro_bind_extra: Vec<PathBuf>,
+    memory_limit: resource::rlim_t,
+    pass_env: Vec<String>,
 }
 
 pub struct SpawnedSandbox {
@@ -146,15 +237,80 @@ pub struct SpawnedSandbox {
 pub struct SandboxInfo {
     pub command_dbg: String,
     pub seccomp_fd: Option<Memfd>,
+    /// Thread that reports the results of the seccomp audit
+    pub audit_thread: Option<JoinHandle<()>>,
+}
+
+/// Returns the Landlock ABI version if the kernel supports Landlock
+pub fn landlock_abi_version() -> Option<i32> {
+    landlock::abi_version()
+}
+
+/// Returns `true` if the kernel supports seccomp filters
+pub fn seccomp_supported() -> bool {
+    // Fails with EINVAL if the kernel is built without seccomp
+    unsafe { libc::prctl(libc::PR_GET_SECCOMP, 0, 0, 0, 0) >= 0 }
+}
+
+/// Returns the version reported by bwrap if the binary is available
+pub fn bwrap_version() -> Option<String> {
+    let output = Command::new("bwrap")
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    // Output has the form "bubblewrap 0.8.0"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.split_whitespace().last().map(ToString::to_string)
+}
+
+/// Returns `true` if the builtin sandbox can be used on this system
+pub fn builtin_usable() -> bool {
+    native::user_namespaces_usable()
+}
+
+/// Returns `true` if bwrap can create a sandbox on this system
+///
+/// Creating user namespaces might be restricted on some systems.
+pub fn bwrap_usable() -> bool {
+    Command::new("bwrap")
+        .args([
+            "--unshare-all",
+            "--die-with-parent",
+            "--ro-bind",
+            "/",
+            "/",
+            "true",
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
 }
 
 impl Sandbox {
-    pub fn new(sandbox_mechanism: SandboxMechanism, command: PathBuf, stdin: UnixStream) -> Self {
+    pub fn new(
+        sandbox_mechanism: SandboxMechanism,
+        config: ImageDecoderConfig,
+        stdin: UnixStream,
+    ) -> Self {
         Self {
             sandbox_mechanism,
-            command,
+            command: config.exec.clone(),
+            memory_limit: config
+                .memory_limit
+                .unwrap_or_else(Self::default_memory_limit),
+            config,
             stdin,
             ro_bind_extra: Vec::new(),
+            pass_env: Vec::new(),
         }
     }
 
@@ -162,24 +318,68 @@ impl Sandbox {
         self.ro_bind_extra.push(path);
     }
 
+    /// Set environment variables that are passed into the sandbox
+    pub fn set_pass_env(&mut self, vars: Vec<String>) {
+        self.pass_env = vars;
+    }
+
+    /// Values of environment variables that are passed into the sandbox
+    fn passed_env(&self) -> Vec<(String, OsString)> {
+        self.pass_env
+            .iter()
+            // Don't override variables set for the sandbox
+            .filter(|key| !SANDBOX_ENV.iter().any(|(x, _)| x == key))
+            .filter_map(|key| Some((key.clone(), std::env::var_os(key)?)))
+            .collect()
+    }
+
+    /// Override the memory limit from the loader config
+    pub fn set_memory_limit(&mut self, limit: resource::rlim_t) {
+        self.memory_limit = limit;
+    }
+
+    /// Memory in bytes the loader can use
+    pub fn memory_limit(&self) -> resource::rlim_t {
+        self.memory_limit
+    }
+
     pub async fn spawn(self) -> crate::Result<SpawnedSandbox> {
         // Determine command line args
         let (bin, args, seccomp_fd) = match self.sandbox_mechanism {
             SandboxMechanism::Bwrap => {
+                if audit::enabled() {
+                    eprintln!("glycin seccomp audit: With bwrap, syscalls are only logged to the kernel's audit log");
+                }
+
                 let mut args = self.bwrap_args().await?;
 
-                let seccomp_memfd = Self::seccomp_export_bpf(&Self::seccomp_filter()?)?;
+                let seccomp_memfd = Self::seccomp_export_bpf(&self.seccomp_filter()?)?;
                 args.push("--seccomp".into());
                 args.push(seccomp_memfd.as_raw_fd().to_string().into());
 
-                args.push(self.command);
+                args.push(self.command.clone());
 
                 ("bwrap".into(), args, Some(seccomp_memfd))
             }
             SandboxMechanism::FlatpakSpawn => {
-                let memory_limit = Self::memory_limit();
+                if audit::enabled() {
+                    eprintln!("glycin seccomp audit: Not supported with flatpak-spawn");
+                }
+
+                let memory_limit = self.memory_limit;
+                let cpu_limit = self.cpu_limit();
 
-                let args = vec![
+                let mut args: Vec<PathBuf> = self
+                    .passed_env()
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let mut arg = OsString::from(format!("--env={key}="));
+                        arg.push(value);
+                        arg.into()
+                    })
+                    .collect();
+
+                args.extend([
                     "--sandbox".into(),
                     // die with parent
                     "--watch-bus".into(),
@@ -188,31 +388,111 @@ impl Sandbox {
                     // Start loader with memory limit
                     "prlimit".into(),
                     format!("--as={memory_limit}").into(),
-                    // Loader binary
-                    self.command,
-                ];
+                ]);
+
+                if let Some(cpu_limit) = cpu_limit {
+                    args.push(format!("--cpu={cpu_limit}").into());
+                }
+
+                // Loader binary
+                args.push(self.command.clone());
 
                 ("flatpak-spawn".into(), args, None)
             }
+            SandboxMechanism::Landlock
+            | SandboxMechanism::Builtin
+            | SandboxMechanism::SeccompOnly => (self.command.clone(), vec![], None),
             SandboxMechanism::NotSandboxed => {
                 eprintln!("WARNING: Glycin running without sandbox.");
-                (self.command, vec![], None)
+                (self.command.clone(), vec![], None)
             }
         };
 
         let mut command = Command::new(bin);
-        command.stdin(OwnedFd::from(self.stdin));
+        // Loader output is forwarded and attached to errors
+        command.stderr(Stdio::piped());
         command.args(args);
 
-        // Set memory limit for sandbox
-        if matches!(self.sandbox_mechanism, SandboxMechanism::Bwrap) {
-            unsafe {
-                command.pre_exec(|| {
-                    Self::set_memory_limit();
-                    Ok(())
-                });
+        let memory_limit = self.memory_limit;
+        let cpu_limit = self.cpu_limit();
+        let passed_env = self.passed_env().into_iter().chain(self.font_env());
+
+        let audit_thread = match self.sandbox_mechanism {
+            SandboxMechanism::Bwrap => {
+                // Set memory and CPU limit for sandbox
+                unsafe {
+                    command.pre_exec(move || {
+                        Self::apply_memory_limit(memory_limit);
+                        Self::set_cpu_limit(cpu_limit);
+                        Ok(())
+                    });
+                }
+
+                None
             }
-        }
+            SandboxMechanism::Landlock => {
+                let mut ruleset = Some(landlock::read_only_ruleset(&self.ro_paths().await?)?);
+                let (seccomp, audit_thread) = self.seccomp_program()?;
+
+                command.env_clear();
+                command.envs(passed_env);
+
+                unsafe {
+                    command.pre_exec(move || {
+                        Self::apply_memory_limit(memory_limit);
+                        Self::set_cpu_limit(cpu_limit);
+                        set_parent_death_signal()?;
+                        close_inherited_fds()?;
+                        if let Some(ruleset) = ruleset.take() {
+                            landlock::restrict_self(ruleset)?;
+                        }
+                        seccomp.load()
+                    });
+                }
+
+                audit_thread
+            }
+            SandboxMechanism::Builtin => {
+                let native_sandbox = NativeSandbox::new(&self.mounts().await)?;
+                let (seccomp, audit_thread) = self.seccomp_program()?;
+
+                command.env_clear();
+                command.envs(SANDBOX_ENV.iter().copied());
+                command.envs(passed_env);
+
+                unsafe {
+                    command.pre_exec(move || {
+                        Self::apply_memory_limit(memory_limit);
+                        Self::set_cpu_limit(cpu_limit);
+                        native_sandbox.enter()?;
+                        seccomp.load()
+                    });
+                }
+
+                audit_thread
+            }
+            SandboxMechanism::SeccompOnly => {
+                let (seccomp, audit_thread) = self.seccomp_program()?;
+
+                command.env_clear();
+                command.envs(passed_env);
+
+                unsafe {
+                    command.pre_exec(move || {
+                        Self::apply_memory_limit(memory_limit);
+                        Self::set_cpu_limit(cpu_limit);
+                        set_parent_death_signal()?;
+                        close_inherited_fds()?;
+                        seccomp.load()
+                    });
+                }
+
+                audit_thread
+            }
+            SandboxMechanism::FlatpakSpawn | SandboxMechanism::NotSandboxed => None,
+        };
+
+        command.stdin(OwnedFd::from(self.stdin));
 
         let command_dbg = format!("{:?}", command);
         let child = command.spawn().map_err(|err| Error::SpawnError {
@@ -225,94 +505,234 @@ impl Sandbox {
             info: SandboxInfo {
                 command_dbg,
                 seccomp_fd,
+                audit_thread,
             },
         })
     }
 
-    async fn bwrap_args(&self) -> crate::Result<Vec<PathBuf>> {
-        let mut args: Vec<PathBuf> = Vec::new();
-
-        args.extend(
-            [
-                "--unshare-all",
-                "--clearenv",
-                "--die-with-parent",
-                // change working directory to something that exists
-                "--chdir",
-                "/",
-                // Make /usr available as read only
-                "--ro-bind",
-                "/usr",
-                "/usr",
-                // Make tmpfs dev available
-                "--dev",
-                "/dev",
-                // Additional linker configuration via /etc/ld.so.conf if available
-                "--ro-bind-try",
-                "/etc/ld.so.cache",
-                "/etc/ld.so.cache",
-                // Create a fake HOME for glib to not throw warnings
-                "--tmpfs",
-                "/tmp-home",
-                "--setenv",
-                "HOME",
-                "/tmp-home",
-                // Create a fake runtime dir for glib to not throw warnings
-                "--tmpfs",
-                "/tmp-run",
-                "--setenv",
-                "XDG_RUNTIME_DIR",
-                "/tmp-run",
-                // Fontconfig
-                "--ro-bind-try",
-                "/etc/fonts",
-                "/etc/fonts",
-                "--ro-bind-try",
-                "/var/cache/fontconfig",
-                "/var/cache/fontconfig",
-            ]
-            .iter()
-            .map(|x| (*x).into())
-            .collect::<Vec<_>>(),
-        );
+    /// Filesystem layout inside the sandbox
+    ///
+    /// Used by bwrap and the builtin sandbox to create the same environment.
+    async fn mounts(&self) -> Vec<Mount> {
+        let mut mounts = vec![
+            // Make /usr available as read only
+            Mount::ro_bind("/usr"),
+            // Make tmpfs dev available
+            Mount::Dev("/dev".into()),
+            // Additional linker configuration via /etc/ld.so.conf if available
+            Mount::ro_bind_try("/etc/ld.so.cache"),
+            // Create a fake HOME for glib to not throw warnings
+            Mount::Tmpfs("/tmp-home".into()),
+            // Create a fake runtime dir for glib to not throw warnings
+            Mount::Tmpfs("/tmp-run".into()),
+            // Fontconfig
+            Mount::ro_bind_try("/etc/fonts"),
+            Mount::ro_bind_try("/var/cache/fontconfig"),
+        ];
+
+        if self.config.expose_fonts {
+            mounts.extend(Self::user_font_mounts());
+        }
 
         let system_setup_arc = SystemSetup::cached().await;
         let system = system_setup_arc.as_ref().as_ref().unwrap();
 
         // Symlink paths like /usr/lib64 to /lib64
         for (dest, src) in &system.lib_symlinks {
-            args.push("--symlink".into());
-            args.push(src.clone());
-            args.push(dest.clone());
+            mounts.push(Mount::Symlink {
+                target: src.clone(),
+                link: dest.clone(),
+            });
         }
 
         // Mount paths like /lib64 if they exist
         for dir in &system.lib_dirs {
-            args.push("--ro-bind".into());
-            args.push(dir.clone());
-            args.push(dir.clone());
+            mounts.push(Mount::ro_bind(dir));
+        }
+
+        // Interpreter and libraries outside of the system dirs, like on NixOS or Guix.
+        // Paths from the ELF files might not exist and must not stop the sandbox
+        // from starting.
+        let dependencies = self
+            .loader_dependencies()
+            .await
+            .into_iter()
+            .filter(|path| !system.contains(path))
+            .map(|path| store_item(&path).unwrap_or(path))
+            .collect::<BTreeSet<_>>();
+        for path in dependencies {
+            mounts.push(Mount::ro_bind_try(path));
         }
 
         // Make extra dirs available
         for dir in &self.ro_bind_extra {
-            args.push("--ro-bind".into());
-            args.push(dir.clone());
-            args.push(dir.clone());
+            mounts.push(Mount::ro_bind(dir));
         }
 
         // Make loader binary available if not in /usr. This is useful for testing and
         // adding loaders in user (/home) configurations.
         if !self.command.starts_with("/usr") {
-            args.push("--ro-bind".into());
-            args.push(self.command.clone());
-            args.push(self.command.clone());
+            mounts.push(Mount::ro_bind(&self.command));
+        }
+
+        mounts
+    }
+
+    /// Files the dynamic linker needs to start the loader
+    ///
+    /// If the loader itself is part of a store, the whole package is included.
+    async fn loader_dependencies(&self) -> Vec<PathBuf> {
+        let command = self.command.clone();
+
+        // Reading the ELF files is blocking I/O
+        spawn_blocking(move || {
+            let Ok(command) = canonicalize(command) else {
+                return Vec::new();
+            };
+
+            let cached = LOADER_DEPENDENCIES
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .get(&command)
+                .cloned();
+            if let Some(dependencies) = cached {
+                return dependencies;
+            }
+
+            let mut dependencies = elf::dependencies(&command);
+            if let Some(item) = store_item(&command) {
+                dependencies.push(item);
+            }
+
+            LOADER_DEPENDENCIES
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .insert(command, dependencies.clone());
+
+            dependencies
+        })
+        .await
+    }
+
+    /// Font directories and fontconfig files of the user
+    ///
+    /// The XDG dirs are mounted to the same locations and passed via
+    /// [`Self::font_env`]. Since `HOME` is different inside the sandbox,
+    /// `~/.fonts` is mounted into the sandbox's home.
+    fn user_font_mounts() -> Vec<Mount> {
+        vec![
+            Mount::ro_bind_try(glib::user_data_dir().join("fonts")),
+            Mount::ro_bind_try(glib::user_config_dir().join("fontconfig")),
+            Mount::ro_bind_try(glib::user_cache_dir().join("fontconfig")),
+            Mount::ro_bind_try_to(glib::home_dir().join(".fonts"), "/tmp-home/.fonts"),
+        ]
+    }
+
+    /// Environment variables for fontconfig to find the user's fonts
+    fn font_env(&self) -> Vec<(String, OsString)> {
+        if !self.config.expose_fonts {
+            return Vec::new();
+        }
+
+        let mut env = vec![
+            ("XDG_DATA_HOME".into(), glib::user_data_dir().into()),
+            ("XDG_CONFIG_HOME".into(), glib::user_config_dir().into()),
+            ("XDG_CACHE_HOME".into(), glib::user_cache_dir().into()),
+        ];
+
+        // Loaders see the host filesystem and find `~/.fonts` in the user's home
+        if matches!(
+            self.sandbox_mechanism,
+            SandboxMechanism::Landlock | SandboxMechanism::SeccompOnly
+        ) {
+            env.push(("HOME".into(), glib::home_dir().into()));
+        }
+
+        env
+    }
+
+    async fn bwrap_args(&self) -> crate::Result<Vec<PathBuf>> {
+        let mut args: Vec<PathBuf> = [
+            "--unshare-all",
+            "--clearenv",
+            "--die-with-parent",
+            // change working directory to something that exists
+            "--chdir",
+            "/",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        for mount in self.mounts().await {
+            match mount {
+                Mount::RoBind {
+                    src,
+                    dest,
+                    optional,
+                } => {
+                    if optional {
+                        args.push("--ro-bind-try".into());
+                    } else {
+                        args.push("--ro-bind".into());
+                    }
+                    args.push(src);
+                    args.push(dest);
+                }
+                Mount::Symlink { target, link } => {
+                    args.push("--symlink".into());
+                    args.push(target);
+                    args.push(link);
+                }
+                Mount::Tmpfs(path) => {
+                    args.push("--tmpfs".into());
+                    args.push(path);
+                }
+                Mount::Dev(path) => {
+                    args.push("--dev".into());
+                    args.push(path);
+                }
+            }
+        }
+
+        for (key, value) in SANDBOX_ENV {
+            args.push("--setenv".into());
+            args.push(key.into());
+            args.push(value.into());
+        }
+
+        for (key, value) in self.passed_env().into_iter().chain(self.font_env()) {
+            args.push("--setenv".into());
+            args.push(key.into());
+            args.push(value.into());
         }
 
         Ok(args)
     }
 
-    /// Memory limit in bytes that should be applied to sandboxes
-    fn memory_limit() -> resource::rlim_t {
+    /// Paths that loaders need read access to
+    ///
+    /// Used for sandboxes that restrict access to the host filesystem instead
+    /// of mounting paths into a new one.
+    async fn ro_paths(&self) -> crate::Result<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = self
+            .mounts()
+            .await
+            .into_iter()
+            .filter_map(|mount| match mount {
+                Mount::RoBind { src, .. } => Some(src),
+                _ => None,
+            })
+            .collect();
+
+        // Symlinks like /lib64 resolve to /usr and need no extra rules
+        paths.extend(["/dev/null", "/dev/urandom"].iter().map(PathBuf::from));
+
+        Ok(paths)
+    }
+
+    /// Memory limit in bytes if not configured otherwise
+    fn default_memory_limit() -> resource::rlim_t {
         // Lookup free memory
         if let Some(mem_available) = Self::mem_available() {
             Self::calculate_memory_limit(mem_available)
@@ -337,9 +757,7 @@ impl Sandbox {
                         .nth(1)
                         .and_then(|x| x.parse::<resource::rlim_t>().ok())
                     {
-                        let mem_available = mem_avail_kb.saturating_mul(1024);
-
-                        return Some(Self::calculate_memory_limit(mem_available));
+                        return Some(mem_avail_kb.saturating_mul(1024));
                     }
                 }
             }
@@ -363,25 +781,155 @@ impl Sandbox {
     }
 
     /// Set memory limit for the current process
-    fn set_memory_limit() {
-        let limit = Self::memory_limit();
-
+    fn apply_memory_limit(limit: resource::rlim_t) {
         if let Err(err) = resource::setrlimit(resource::Resource::RLIMIT_AS, limit, limit) {
             eprintln!("Error setrlimit(RLIMIT_AS, {limit}): {err}");
         }
     }
 
-    fn seccomp_filter() -> Result<ScmpFilterContext, SeccompError> {
-        let mut filter = ScmpFilterContext::new_filter(ScmpAction::Trap)?;
+    /// CPU time in seconds the loader can use
+    ///
+    /// The limit applies to the whole lifetime of the loader, including all
+    /// frames of an animation. Therefore, there is no limit by default.
+    fn cpu_limit(&self) -> Option<resource::rlim_t> {
+        self.config.cpu_limit
+    }
+
+    /// Set CPU time limit for the current process
+    ///
+    /// The process receives `SIGXCPU` when reaching the limit and is killed one
+    /// second later if it doesn't terminate.
+    fn set_cpu_limit(limit: Option<resource::rlim_t>) {
+        let Some(limit) = limit else {
+            return;
+        };
+
+        let hard_limit = limit.saturating_add(1);
+
+        if let Err(err) = resource::setrlimit(resource::Resource::RLIMIT_CPU, limit, hard_limit) {
+            eprintln!("Error setrlimit(RLIMIT_CPU, {limit}): {err}");
+        }
+    }
+
+    /// Syscalls the loader is allowed to use according to its config
+    fn allowed_syscalls(&self) -> BTreeSet<&str> {
+        let mut syscalls: BTreeSet<&str> = ALLOWED_SYSCALLS.iter().copied().collect();
+
+        if self.config.seccomp_profile == SeccompProfile::Strict {
+            for syscall in STRICT_DENIED_SYSCALLS {
+                syscalls.remove(syscall);
+            }
+        }
 
-        for syscall_name in ALLOWED_SYSCALLS {
+        syscalls.extend(self.config.seccomp_allow.iter().map(String::as_str));
+
+        for syscall in &self.config.seccomp_deny {
+            syscalls.remove(syscall.as_str());
+        }
+
+        syscalls
+    }
+
+    fn seccomp_filter(&self) -> Result<ScmpFilterContext, SeccompError> {
+        let default_action = if audit::enabled() {
+            match self.sandbox_mechanism {
+                SandboxMechanism::Bwrap | SandboxMechanism::FlatpakSpawn => ScmpAction::Log,
+                _ => ScmpAction::Notify,
+            }
+        } else {
+            ScmpAction::Trap
+        };
+
+        let mut filter = ScmpFilterContext::new_filter(default_action)?;
+
+        for syscall_name in self.allowed_syscalls() {
             let syscall = ScmpSyscall::from_name(syscall_name)?;
-            filter.add_rule(ScmpAction::Allow, syscall).unwrap();
+
+            if syscall_name == "clone3" {
+                // Arguments of clone3 are passed via a struct and can't be checked. Let
+                // libc fall back to clone.
+                filter.add_rule(ScmpAction::Errno(libc::ENOSYS), syscall)?;
+            } else if let Some(rules) = Self::argument_rules(syscall_name) {
+                for comparators in rules {
+                    filter.add_rule_conditional(ScmpAction::Allow, syscall, &comparators)?;
+                }
+            } else {
+                filter.add_rule(ScmpAction::Allow, syscall)?;
+            }
         }
 
         Ok(filter)
     }
 
+    /// Restrictions on arguments for syscalls
+    ///
+    /// The syscall is allowed if all comparators of any of the returned rules
+    /// match.
+    fn argument_rules(syscall_name: &str) -> Option<Vec<Vec<ScmpArgCompare>>> {
+        let equal = |arg, value| vec![ScmpArgCompare::new(arg, ScmpCompareOp::Equal, value)];
+
+        match syscall_name {
+            // Only allow local communication
+            "socket" => Some(vec![equal(0, seccomp_arg(libc::AF_UNIX))]),
+            "ioctl" => Some(
+                [
+                    libc::FIONBIO,
+                    libc::FIOCLEX,
+                    libc::FIONCLEX,
+                    libc::FIONREAD,
+                    libc::TCGETS,
+                    libc::TIOCGWINSZ,
+                ]
+                .into_iter()
+                .map(|request| {
+                    // Only the lower 32 bit of the request are relevant
+                    vec![ScmpArgCompare::new(
+                        1,
+                        ScmpCompareOp::MaskedEqual(u64::from(u32::MAX)),
+                        seccomp_arg(request),
+                    )]
+                })
+                .collect(),
+            ),
+            // Don't allow creating new user namespaces
+            "clone" => Some(vec![vec![ScmpArgCompare::new(
+                0,
+                ScmpCompareOp::MaskedEqual(seccomp_arg(libc::CLONE_NEWUSER)),
+                0,
+            )]]),
+            "prctl" => Some(
+                [
+                    seccomp_arg(libc::PR_SET_NAME),
+                    seccomp_arg(libc::PR_GET_NAME),
+                    PR_SET_VMA,
+                    seccomp_arg(libc::PR_CAPBSET_READ),
+                    seccomp_arg(libc::PR_SET_NO_NEW_PRIVS),
+                    seccomp_arg(libc::PR_GET_NO_NEW_PRIVS),
+                ]
+                .into_iter()
+                .map(|option| equal(0, option))
+                .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Seccomp filter for sandboxes that load the filter themselves
+    ///
+    /// In audit mode, the thread collecting the syscalls is returned as well.
+    fn seccomp_program(&self) -> crate::Result<(SeccompProgram, Option<JoinHandle<()>>)> {
+        let (audit_socket, audit_thread) = if audit::enabled() {
+            let (socket, thread) = audit::spawn_listener(self.command.clone())?;
+            (Some(socket), Some(thread))
+        } else {
+            (None, None)
+        };
+
+        let program = SeccompProgram::new(&self.seccomp_filter()?, audit_socket)?;
+
+        Ok((program, audit_thread))
+    }
+
     fn seccomp_export_bpf(filter: &ScmpFilterContext) -> crate::Result<Memfd> {
         let mut memfd = MemfdOptions::default()
             .close_on_exec(false)
@@ -396,6 +944,115 @@ impl Sandbox {
     }
 }
 
+/// Seccomp filter that can be loaded between fork and exec
+///
+/// libseccomp allocates when loading filters. Therefore, the BPF program is
+/// generated beforehand and loaded directly via `prctl`.
+struct SeccompProgram {
+    filter: Vec<libc::sock_filter>,
+    /// Socket to pass the notification fd to glycin in audit mode
+    audit_socket: Option<UnixStream>,
+}
+
+impl SeccompProgram {
+    fn new(filter: &ScmpFilterContext, audit_socket: Option<UnixStream>) -> crate::Result<Self> {
+        let memfd = Sandbox::seccomp_export_bpf(filter)?;
+        let mut bpf = Vec::new();
+        memfd.as_file().read_to_end(&mut bpf)?;
+
+        let filter = bpf
+            .chunks_exact(std::mem::size_of::<libc::sock_filter>())
+            .map(|x| libc::sock_filter {
+                code: u16::from_ne_bytes([x[0], x[1]]),
+                jt: x[2],
+                jf: x[3],
+                k: u32::from_ne_bytes([x[4], x[5], x[6], x[7]]),
+            })
+            .collect();
+
+        Ok(Self {
+            filter,
+            audit_socket,
+        })
+    }
+
+    /// Load filter for the current process
+    fn load(&self) -> io::Result<()> {
+        let program = libc::sock_fprog {
+            len: u16::try_from(self.filter.len())
+                .map_err(|_| io::Error::from_raw_os_error(libc::E2BIG))?,
+            filter: self.filter.as_ptr().cast_mut(),
+        };
+
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some(audit_socket) = &self.audit_socket {
+                return audit::load_filter_with_listener(&program, audit_socket);
+            }
+
+            if libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            ) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Convert constants for comparison with syscall arguments
+fn seccomp_arg(value: impl TryInto<u64>) -> u64 {
+    // All used constants are positive
+    value.try_into().unwrap_or(u64::MAX)
+}
+
+/// Kill the current process when the parent terminates
+fn set_parent_death_signal() -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Don't pass fds other than stdin, stdout, and stderr to the loader
+///
+/// The fds are marked as close-on-exec instead of closing them, since the
+/// seccomp audit socket is still needed before exec.
+fn close_inherited_fds() -> io::Result<()> {
+    let result =
+        unsafe { libc::syscall(libc::SYS_close_range, 3_u32, u32::MAX, CLOSE_RANGE_CLOEXEC) };
+
+    if result == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    if !matches!(err.raw_os_error(), Some(libc::ENOSYS | libc::EINVAL)) {
+        return Err(err);
+    }
+
+    // Fallback for kernels older than 5.11
+    let max_fd = match unsafe { libc::sysconf(libc::_SC_OPEN_MAX) } {
+        -1 => 1024,
+        n => libc::c_int::try_from(n.min(65536)).unwrap_or(1024),
+    };
+
+    for fd in 3..max_fd {
+        // Fails for fds that are not open
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Default)]
 struct SystemSetup {
     // Dirs that need to be symlinked (UsrMerge)
@@ -405,6 +1062,17 @@ struct SystemSetup {
 }
 
 impl SystemSetup {
+    /// Returns `true` if the path is part of the lib dirs that are always
+    /// available in the sandbox
+    fn contains(&self, path: &Path) -> bool {
+        path.starts_with("/usr")
+            || self.lib_dirs.iter().any(|dir| path.starts_with(dir))
+            || self
+                .lib_symlinks
+                .iter()
+                .any(|(link, _)| path.starts_with(link))
+    }
+
     async fn cached() -> Arc<io::Result<SystemSetup>> {
         let mut system_setup = SYSTEM_SETUP.lock().await;
 
@@ -470,3 +1138,13 @@ impl SystemSetup {
         Ok(())
     }
 }
+
+/// Package in a store dir that contains the path
+///
+/// For `/nix/store/<hash>-glibc/lib/libc.so.6` this is `/nix/store/<hash>-glibc`.
+fn store_item(path: &Path) -> Option<PathBuf> {
+    STORE_DIRS.iter().find_map(|store| {
+        let item = path.strip_prefix(store).ok()?.components().next()?;
+        Some(Path::new(store).join(item))
+    })
+}
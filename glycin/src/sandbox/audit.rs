@@ -0,0 +1,228 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Collect syscalls that are not covered by the seccomp filter
+//!
+//! Enabled by setting the `GLYCIN_SECCOMP_AUDIT` environment variable. For
+//! sandboxes that apply the filter themselves, the filter notifies glycin
+//! about syscalls that would be blocked and lets them pass. Once the loader
+//! terminates, the syscalls are printed as a suggestion for the loader's
+//! `SeccompAllow` config. If the variable contains a path, the syscalls are
+//! also appended to that file in the form `<loader> <syscall>`.
+//!
+//! With bwrap, syscalls that would be blocked are only logged to the kernel's
+//! audit log.
+
+use std::collections::BTreeSet;
+use std::fs::OpenOptions;
+use std::io::{self, IoSliceMut, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+
+use libseccomp::{ScmpNotifReq, ScmpNotifResp, ScmpNotifRespFlags};
+use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
+
+const ENV_VAR: &str = "GLYCIN_SECCOMP_AUDIT";
+
+const SECCOMP_SET_MODE_FILTER: libc::c_uint = 1;
+const SECCOMP_FILTER_FLAG_NEW_LISTENER: libc::c_uint = 8;
+
+pub fn enabled() -> bool {
+    std::env::var_os(ENV_VAR).is_some()
+}
+
+/// File the audit results are appended to
+fn report_file() -> Option<PathBuf> {
+    std::env::var_os(ENV_VAR)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+/// Listen for syscalls the loader uses that are not part of the filter
+///
+/// The returned socket has to be passed to [`load_filter_with_listener`]. The
+/// thread finishes once the results are reported.
+pub fn spawn_listener(loader: PathBuf) -> io::Result<(UnixStream, JoinHandle<()>)> {
+    let (sender, receiver) = UnixStream::pair()?;
+
+    let thread = std::thread::Builder::new()
+        .name(String::from("glycin-seccomp-audit"))
+        .spawn(move || {
+            let listener = match receive_fd(&receiver) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    eprintln!("glycin seccomp audit: Failed to receive listener: {err}");
+                    return;
+                }
+            };
+
+            let syscalls = collect_syscalls(listener.as_raw_fd());
+            report(&loader, &syscalls);
+        })?;
+
+    Ok((sender, thread))
+}
+
+/// Install filter and send the notification fd to glycin
+///
+/// This is called between fork and exec.
+pub fn load_filter_with_listener(
+    program: &libc::sock_fprog,
+    socket: &UnixStream,
+) -> io::Result<()> {
+    let listener = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            SECCOMP_FILTER_FLAG_NEW_LISTENER,
+            program as *const libc::sock_fprog,
+        )
+    };
+
+    if listener < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let listener =
+        RawFd::try_from(listener).map_err(|_| io::Error::from_raw_os_error(libc::EBADF))?;
+    let result = send_fd(socket.as_raw_fd(), listener);
+    unsafe { libc::close(listener) };
+
+    result
+}
+
+/// Send fd via `SCM_RIGHTS` without allocating
+fn send_fd(socket: RawFd, fd: RawFd) -> io::Result<()> {
+    let fd_len = u32::try_from(std::mem::size_of::<RawFd>())
+        .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+
+    // Aligned buffer that is large enough for a control message with one fd
+    let mut cmsg_buf = [0_u64; 4];
+    let mut data = [0_u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr().cast();
+        msg.msg_controllen = libc::CMSG_SPACE(fd_len)
+            .try_into()
+            .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len)
+            .try_into()
+            .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+
+        if libc::sendmsg(socket, &msg, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+fn receive_fd(socket: &UnixStream) -> io::Result<OwnedFd> {
+    let mut data = [0_u8; 1];
+    let mut iov = [IoSliceMut::new(&mut data)];
+    let mut cmsg_buf = nix::cmsg_space!(RawFd);
+
+    let msg = recvmsg::<()>(
+        socket.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg_buf),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )?;
+
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::ScmRights(fds) = cmsg {
+            if let Some(fd) = fds.first() {
+                return Ok(unsafe { OwnedFd::from_raw_fd(*fd) });
+            }
+        }
+    }
+
+    // Loader exited or failed before installing the filter
+    Err(io::Error::from(io::ErrorKind::UnexpectedEof))
+}
+
+/// Let all notified syscalls pass and return their names
+fn collect_syscalls(listener: RawFd) -> BTreeSet<String> {
+    let mut syscalls = BTreeSet::new();
+
+    loop {
+        let mut pollfd = libc::pollfd {
+            fd: listener,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        if unsafe { libc::poll(&mut pollfd, 1, -1) } < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+
+        // Otherwise POLLHUP: No process uses the filter anymore
+        if pollfd.revents & libc::POLLIN == 0 {
+            break;
+        }
+
+        // Fails if the process was killed in the meantime
+        let Ok(request) = ScmpNotifReq::receive(listener) else {
+            continue;
+        };
+
+        let name = request
+            .data
+            .syscall
+            .get_name_by_arch(request.data.arch)
+            .unwrap_or_else(|_| format!("{:?}", request.data.syscall));
+        syscalls.insert(name);
+
+        let response = ScmpNotifResp::new_continue(request.id, ScmpNotifRespFlags::empty());
+        let _result = response.respond(listener);
+    }
+
+    syscalls
+}
+
+fn report(loader: &Path, syscalls: &BTreeSet<String>) {
+    if syscalls.is_empty() {
+        eprintln!("glycin seccomp audit: {loader:?} only used allowed syscalls");
+        return;
+    }
+
+    let list = syscalls.iter().cloned().collect::<Vec<_>>();
+    eprintln!(
+        "glycin seccomp audit: {loader:?} used syscalls that are not allowed: {}\nSuggested loader config: SeccompAllow = {};",
+        list.join(", "),
+        list.join(";")
+    );
+
+    if let Some(path) = report_file() {
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| {
+                for syscall in syscalls {
+                    writeln!(file, "{} {syscall}", loader.display())?;
+                }
+                Ok(())
+            });
+
+        if let Err(err) = result {
+            eprintln!("glycin seccomp audit: Failed to write {path:?}: {err}");
+        }
+    }
+}
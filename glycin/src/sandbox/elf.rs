@@ -0,0 +1,394 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Find the files the dynamic linker needs to start a loader
+//!
+//! On distributions like NixOS or Guix, the ELF interpreter and libraries are
+//! not located in `/usr` but in store paths like `/nix/store`. Only the parts
+//! of ELF files needed to find the interpreter and resolve `DT_NEEDED` entries
+//! via `DT_RUNPATH` or `DT_RPATH` are parsed.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// Upper bound for strings like paths read from ELF files
+const MAX_STRING_LEN: usize = 4096;
+
+/// Interpreter and libraries a binary is linked against
+///
+/// Only libraries found via the runpath of the binary or its libraries are
+/// part of the result. Libraries from the system's default dirs or
+/// `/etc/ld.so.cache` are expected to be available in the sandbox already.
+pub fn dependencies(binary: &Path) -> Vec<PathBuf> {
+    let mut result = BTreeSet::new();
+    let mut queue = VecDeque::from([binary.to_path_buf()]);
+    let mut visited = BTreeSet::new();
+
+    while let Some(path) = queue.pop_front() {
+        if !visited.insert(path.clone()) {
+            continue;
+        }
+
+        let info = match DynamicInfo::load(&path) {
+            Ok(info) => info,
+            Err(err) => {
+                eprintln!("glycin: Failed to read dependencies of {path:?}: {err}");
+                continue;
+            }
+        };
+
+        if let Some(interpreter) = &info.interpreter {
+            insert_with_target(&mut result, interpreter);
+        }
+
+        let origin = path.parent().unwrap_or(Path::new("/"));
+        let search_dirs = info
+            .search_paths()
+            .map(|dir| expand_origin(dir, origin))
+            .collect::<Vec<_>>();
+
+        for needed in &info.needed {
+            if needed.contains('/') {
+                let lib = expand_origin(needed, origin);
+                insert_with_target(&mut result, &lib);
+                queue.push_back(lib);
+                continue;
+            }
+
+            if let Some(lib) = search_dirs
+                .iter()
+                .map(|dir| dir.join(needed))
+                .find(|lib| lib.is_file())
+            {
+                insert_with_target(&mut result, &lib);
+                queue.push_back(lib);
+            }
+        }
+    }
+
+    result.into_iter().collect()
+}
+
+/// Insert path and, if it's a symlink, the file it points to
+fn insert_with_target(paths: &mut BTreeSet<PathBuf>, path: &Path) {
+    if let Ok(target) = path.canonicalize() {
+        paths.insert(target);
+    }
+    paths.insert(path.to_path_buf());
+}
+
+/// Replace `$ORIGIN` by the directory of the ELF file
+fn expand_origin(path: &str, origin: &Path) -> PathBuf {
+    let origin = origin.to_string_lossy();
+    path.replace("${ORIGIN}", &origin)
+        .replace("$ORIGIN", &origin)
+        .into()
+}
+
+#[derive(Debug, Default)]
+struct DynamicInfo {
+    interpreter: Option<PathBuf>,
+    needed: Vec<String>,
+    runpath: Option<String>,
+    rpath: Option<String>,
+}
+
+impl DynamicInfo {
+    fn load(path: &Path) -> io::Result<Self> {
+        let elf = Elf::open(path)?;
+        let segments = elf.program_headers()?;
+
+        let mut info = Self::default();
+        let mut dynamic = Vec::new();
+
+        for segment in &segments {
+            match segment.kind {
+                PT_INTERP => {
+                    let interpreter = elf.string(segment.offset)?;
+                    info.interpreter = Some(interpreter.into());
+                }
+                PT_DYNAMIC => {
+                    dynamic = elf.dynamic_entries(segment)?;
+                }
+                _ => {}
+            }
+        }
+
+        let Some(strtab) = dynamic
+            .iter()
+            .find(|(tag, _)| *tag == DT_STRTAB)
+            .map(|(_, addr)| *addr)
+        else {
+            return Ok(info);
+        };
+
+        // The string table is referenced by its address in memory
+        let strtab = segments
+            .iter()
+            .filter(|segment| segment.kind == PT_LOAD)
+            .find_map(|segment| {
+                let offset = strtab.checked_sub(segment.vaddr)?;
+                (offset < segment.filesz).then(|| segment.offset.checked_add(offset))?
+            })
+            .ok_or_else(|| invalid("String table outside of loaded segments"))?;
+
+        for (tag, value) in dynamic {
+            let string = || elf.string(strtab.checked_add(value).ok_or_else(overflow)?);
+
+            match tag {
+                DT_NEEDED => info.needed.push(string()?),
+                DT_RUNPATH => info.runpath = Some(string()?),
+                DT_RPATH => info.rpath = Some(string()?),
+                _ => {}
+            }
+        }
+
+        Ok(info)
+    }
+
+    /// Dirs in the order the dynamic linker searches them
+    ///
+    /// The rpath is ignored if a runpath is set.
+    fn search_paths(&self) -> impl Iterator<Item = &str> {
+        self.runpath
+            .as_ref()
+            .or(self.rpath.as_ref())
+            .into_iter()
+            .flat_map(|paths| paths.split(':'))
+            .filter(|dir| !dir.is_empty())
+    }
+}
+
+struct Segment {
+    kind: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+struct Elf {
+    file: File,
+    is_64: bool,
+    is_le: bool,
+}
+
+impl Elf {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        let mut ident = [0; 16];
+        file.read_exact_at(&mut ident, 0)?;
+
+        if ident[..4] != *b"\x7fELF" {
+            return Err(invalid("Not an ELF file"));
+        }
+
+        let is_64 = match ident[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(invalid("Unknown ELF class")),
+        };
+
+        let is_le = match ident[5] {
+            1 => true,
+            2 => false,
+            _ => return Err(invalid("Unknown ELF data encoding")),
+        };
+
+        Ok(Self { file, is_64, is_le })
+    }
+
+    fn program_headers(&self) -> io::Result<Vec<Segment>> {
+        let (phoff, phentsize, phnum) = if self.is_64 {
+            (self.u64_at(0x20)?, self.u16_at(0x36)?, self.u16_at(0x38)?)
+        } else {
+            (
+                self.u32_at(0x1c)?.into(),
+                self.u16_at(0x2a)?,
+                self.u16_at(0x2c)?,
+            )
+        };
+
+        (0..phnum)
+            .map(|i| {
+                let header = u64::from(i)
+                    .checked_mul(phentsize.into())
+                    .and_then(|x| x.checked_add(phoff))
+                    .ok_or_else(overflow)?;
+                let at = |offset: u64| header.checked_add(offset).ok_or_else(overflow);
+
+                let kind = self.u32_at(header)?;
+                if self.is_64 {
+                    Ok(Segment {
+                        kind,
+                        offset: self.u64_at(at(0x08)?)?,
+                        vaddr: self.u64_at(at(0x10)?)?,
+                        filesz: self.u64_at(at(0x20)?)?,
+                    })
+                } else {
+                    Ok(Segment {
+                        kind,
+                        offset: self.u32_at(at(0x04)?)?.into(),
+                        vaddr: self.u32_at(at(0x08)?)?.into(),
+                        filesz: self.u32_at(at(0x10)?)?.into(),
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Tag and value pairs of the dynamic section
+    fn dynamic_entries(&self, segment: &Segment) -> io::Result<Vec<(u64, u64)>> {
+        let entry_size: u64 = if self.is_64 { 16 } else { 8 };
+        let mut entries = Vec::new();
+
+        let n_entries = segment.filesz.checked_div(entry_size).unwrap_or_default();
+
+        for i in 0..n_entries {
+            let entry = i
+                .checked_mul(entry_size)
+                .and_then(|x| x.checked_add(segment.offset))
+                .ok_or_else(overflow)?;
+
+            let (tag, value) = if self.is_64 {
+                let value = entry.checked_add(8).ok_or_else(overflow)?;
+                (self.u64_at(entry)?, self.u64_at(value)?)
+            } else {
+                let value = entry.checked_add(4).ok_or_else(overflow)?;
+                (self.u32_at(entry)?.into(), self.u32_at(value)?.into())
+            };
+
+            if tag == DT_NULL {
+                break;
+            }
+
+            entries.push((tag, value));
+        }
+
+        Ok(entries)
+    }
+
+    /// Read NUL terminated string
+    fn string(&self, offset: u64) -> io::Result<String> {
+        let mut buf = vec![0; MAX_STRING_LEN];
+        let len = self.file.read_at(&mut buf, offset)?;
+        buf.truncate(len);
+
+        let end = buf
+            .iter()
+            .position(|x| *x == 0)
+            .ok_or_else(|| invalid("Unterminated string"))?;
+        buf.truncate(end);
+
+        String::from_utf8(buf).map_err(|_| invalid("String is not UTF-8"))
+    }
+
+    fn bytes_at<const N: usize>(&self, offset: u64) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.file.read_exact_at(&mut buf, offset)?;
+        Ok(buf)
+    }
+
+    fn u16_at(&self, offset: u64) -> io::Result<u16> {
+        let bytes = self.bytes_at(offset)?;
+        Ok(if self.is_le {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, offset: u64) -> io::Result<u32> {
+        let bytes = self.bytes_at(offset)?;
+        Ok(if self.is_le {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn u64_at(&self, offset: u64) -> io::Result<u64> {
+        let bytes = self.bytes_at(offset)?;
+        Ok(if self.is_le {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn overflow() -> io::Error {
+    invalid("Offset out of range")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SELF_EXE: &str = "/proc/self/exe";
+
+    /// Temporary file that is removed when dropped, even if the test fails
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("glycin-elf-{name}-{}", std::process::id()));
+            std::fs::write(&path, data).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn self_exe() {
+        let info = DynamicInfo::load(Path::new(SELF_EXE)).unwrap();
+        let interpreter = info.interpreter.unwrap();
+
+        assert!(interpreter.is_absolute());
+        assert!(!info.needed.is_empty());
+
+        let dependencies = dependencies(Path::new(SELF_EXE));
+        assert!(dependencies.contains(&interpreter));
+    }
+
+    #[test]
+    fn garbage() {
+        let file = TempFile::new("garbage", b"\x7fELF but not really an ELF file");
+
+        let err = DynamicInfo::load(&file.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(dependencies(&file.0).is_empty());
+    }
+
+    #[test]
+    fn truncated() {
+        // Only the ELF header without program headers
+        let data = std::fs::read(SELF_EXE).unwrap();
+        let file = TempFile::new("truncated", &data[..64]);
+
+        assert!(DynamicInfo::load(&file.0).is_err());
+        assert!(dependencies(&file.0).is_empty());
+    }
+}
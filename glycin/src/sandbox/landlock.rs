@@ -0,0 +1,62 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Filesystem restrictions via Landlock
+//!
+//! Landlock doesn't require user namespaces and can therefore be used on
+//! systems where bwrap is not usable.
+
+use std::io;
+use std::path::PathBuf;
+
+use ::landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreated, RulesetCreatedAttr,
+    RulesetError, RulesetStatus, ABI,
+};
+
+/// Newest ABI version that is used. Older kernels get restrictions on a best
+/// effort basis.
+const ABI_VERSION: ABI = ABI::V3;
+
+/// Flag for `landlock_create_ruleset` to query the ABI version
+const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+
+/// Returns the Landlock ABI version supported by the kernel
+///
+/// Returns `None` if Landlock is not supported or disabled.
+pub fn abi_version() -> Option<i32> {
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<libc::c_void>(),
+            0_usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+
+    i32::try_from(version).ok().filter(|x| *x > 0)
+}
+
+/// Ruleset that only allows reading and executing the given paths
+///
+/// Paths that don't exist are ignored.
+pub fn read_only_ruleset(paths: &[PathBuf]) -> Result<RulesetCreated, RulesetError> {
+    let paths = paths.iter().filter(|x| x.exists());
+
+    Ruleset::default()
+        .handle_access(AccessFs::from_all(ABI_VERSION))?
+        .create()?
+        .add_rules(path_beneath_rules(paths, AccessFs::from_read(ABI_VERSION)))
+}
+
+/// Apply ruleset to the current process
+///
+/// This is called between fork and exec and therefore shouldn't allocate.
+pub fn restrict_self(ruleset: RulesetCreated) -> io::Result<()> {
+    let status = ruleset.restrict_self().map_err(io::Error::other)?;
+
+    if status.ruleset == RulesetStatus::NotEnforced {
+        return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,497 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Builtin sandbox that doesn't depend on the bwrap binary
+//!
+//! Creates the same filesystem layout as bwrap via namespaces and
+//! `pivot_root`. Everything is prepared in the parent process such that the
+//! part running between fork and exec doesn't need to allocate.
+
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use super::Mount;
+
+/// Device nodes that are made available in the sandbox's `/dev`
+const DEV_NODES: &[&str] = &["null", "zero", "full", "random", "urandom", "tty"];
+
+const NAMESPACES: libc::c_int = libc::CLONE_NEWUSER
+    | libc::CLONE_NEWNS
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWCGROUP;
+
+/// Attributes for `mount_setattr`, not available in the libc crate
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+const MOUNT_ATTR_NOSUID: u64 = 0x2;
+
+/// Where the new root is assembled
+const NEW_ROOT: &str = "/newroot";
+/// Where the host's root is available while assembling the new root
+const OLD_ROOT: &str = "/oldroot";
+
+#[derive(Debug)]
+enum Step {
+    Mkdir(CString),
+    /// Create an empty file as mount point
+    Touch(CString),
+    BindRo {
+        src: CString,
+        dest: CString,
+    },
+    BindRw {
+        src: CString,
+        dest: CString,
+    },
+    Tmpfs(CString),
+    Symlink {
+        target: CString,
+        link: CString,
+    },
+}
+
+/// `struct mount_attr` of `mount_setattr`
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+#[derive(Debug)]
+pub struct NativeSandbox {
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    parent_pid: libc::pid_t,
+    steps: Vec<Step>,
+}
+
+impl NativeSandbox {
+    pub fn new(mounts: &[Mount]) -> io::Result<Self> {
+        let uid = unsafe { libc::getuid() };
+        let gid = unsafe { libc::getgid() };
+
+        let mut sandbox = Self {
+            uid_map: format!("{uid} {uid} 1").into_bytes(),
+            gid_map: format!("{gid} {gid} 1").into_bytes(),
+            parent_pid: unsafe { libc::getpid() },
+            steps: Vec::new(),
+        };
+
+        let mut created_dirs = HashSet::new();
+
+        for mount in mounts {
+            match mount {
+                Mount::RoBind {
+                    src,
+                    dest,
+                    optional,
+                } => {
+                    // Resolve symlinks since absolute links don't work below the old root
+                    let src = match std::fs::canonicalize(src) {
+                        Ok(src) => src,
+                        Err(_) if *optional => continue,
+                        Err(err) => {
+                            return Err(io::Error::new(
+                                err.kind(),
+                                format!("Can't bind {src:?}: {err}"),
+                            ))
+                        }
+                    };
+
+                    if src.is_dir() {
+                        sandbox.mkdir_all(&mut created_dirs, dest)?;
+                    } else {
+                        sandbox.mkdir_parent(&mut created_dirs, dest)?;
+                        sandbox.steps.push(Step::Touch(new_root_path(dest)?));
+                    }
+
+                    sandbox.steps.push(Step::BindRo {
+                        src: old_root_path(&src)?,
+                        dest: new_root_path(dest)?,
+                    });
+                }
+                Mount::Symlink { target, link } => {
+                    sandbox.mkdir_parent(&mut created_dirs, link)?;
+                    sandbox.steps.push(Step::Symlink {
+                        target: cstring(target)?,
+                        link: new_root_path(link)?,
+                    });
+                }
+                Mount::Tmpfs(path) => {
+                    sandbox.mkdir_all(&mut created_dirs, path)?;
+                    sandbox.steps.push(Step::Tmpfs(new_root_path(path)?));
+                }
+                Mount::Dev(path) => {
+                    sandbox.mkdir_all(&mut created_dirs, path)?;
+                    sandbox.steps.push(Step::Tmpfs(new_root_path(path)?));
+
+                    for node in DEV_NODES {
+                        let host_node = Path::new("/dev").join(node);
+                        if !host_node.exists() {
+                            continue;
+                        }
+
+                        let dest = path.join(node);
+                        sandbox.steps.push(Step::Touch(new_root_path(&dest)?));
+                        sandbox.steps.push(Step::BindRw {
+                            src: old_root_path(&host_node)?,
+                            dest: new_root_path(&dest)?,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(sandbox)
+    }
+
+    /// Move the current process into the sandbox
+    ///
+    /// This is called between fork and exec. Since only children are moved
+    /// into a new PID namespace, the process forks once more. Only the child
+    /// returns and the parent stays outside the namespace and exits with the
+    /// child's status.
+    pub fn enter(&self) -> io::Result<()> {
+        check(unsafe { libc::unshare(NAMESPACES) })?;
+
+        // Map the current user into the new user namespace
+        write_file(cstr(b"/proc/self/setgroups\0"), b"deny")?;
+        write_file(cstr(b"/proc/self/uid_map\0"), &self.uid_map)?;
+        write_file(cstr(b"/proc/self/gid_map\0"), &self.gid_map)?;
+
+        // Don't propagate any mounts to the host
+        mount(None, cstr(b"/\0"), None, libc::MS_SLAVE | libc::MS_REC)?;
+
+        // Assemble new root in a tmpfs
+        mount(
+            Some(cstr(b"tmpfs\0")),
+            cstr(b"/tmp\0"),
+            Some(cstr(b"tmpfs\0")),
+            libc::MS_NOSUID | libc::MS_NODEV,
+        )?;
+        check(unsafe { libc::chdir(cstr(b"/tmp\0").as_ptr()) })?;
+        check(unsafe { libc::mkdir(cstr(b"newroot\0").as_ptr(), 0o755) })?;
+        check(unsafe { libc::mkdir(cstr(b"oldroot\0").as_ptr(), 0o755) })?;
+        mount(
+            Some(cstr(b"newroot\0")),
+            cstr(b"newroot\0"),
+            None,
+            libc::MS_BIND | libc::MS_REC | libc::MS_NOSUID,
+        )?;
+        pivot_root(cstr(b".\0"), cstr(b"oldroot\0"))?;
+        check(unsafe { libc::chdir(cstr(b"/\0").as_ptr()) })?;
+
+        for step in &self.steps {
+            step.run()?;
+        }
+
+        // Remove access to the host's root
+        check(unsafe { libc::umount2(cstr(b"/oldroot\0").as_ptr(), libc::MNT_DETACH) })?;
+
+        // Make the assembled directory the root
+        check(unsafe { libc::chdir(cstr(b"/newroot\0").as_ptr()) })?;
+        pivot_root(cstr(b".\0"), cstr(b".\0"))?;
+        check(unsafe { libc::umount2(cstr(b".\0").as_ptr(), libc::MNT_DETACH) })?;
+        check(unsafe { libc::chdir(cstr(b"/\0").as_ptr()) })?;
+
+        // Entering the user namespace resets the parent death signal
+        check(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) })?;
+        if unsafe { libc::getppid() } != self.parent_pid {
+            return Err(io::Error::from_raw_os_error(libc::ESRCH));
+        }
+
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => {
+                // The parent death signal is not inherited
+                check(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) })
+            }
+            pid => forward_exit(pid),
+        }
+    }
+
+    fn mkdir_all(&mut self, created: &mut HashSet<PathBuf>, path: &Path) -> io::Result<()> {
+        for dir in path.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            if dir == Path::new("/") || created.contains(dir) {
+                continue;
+            }
+
+            created.insert(dir.to_path_buf());
+            self.steps.push(Step::Mkdir(new_root_path(dir)?));
+        }
+
+        Ok(())
+    }
+
+    fn mkdir_parent(&mut self, created: &mut HashSet<PathBuf>, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            self.mkdir_all(created, parent)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Step {
+    fn run(&self) -> io::Result<()> {
+        match self {
+            Self::Mkdir(path) => match check(unsafe { libc::mkdir(path.as_ptr(), 0o755) }) {
+                // Directory might be part of an earlier bind mount
+                Err(err) if err.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+                result => result,
+            },
+            Self::Touch(path) => {
+                let fd = unsafe {
+                    libc::open(
+                        path.as_ptr(),
+                        libc::O_RDONLY | libc::O_CREAT | libc::O_CLOEXEC,
+                        0o444,
+                    )
+                };
+                check(fd)?;
+                check(unsafe { libc::close(fd) })
+            }
+            Self::BindRo { src, dest } => {
+                mount(
+                    Some(src.as_c_str()),
+                    dest,
+                    None,
+                    libc::MS_BIND | libc::MS_REC,
+                )?;
+
+                match set_ro_recursive(dest) {
+                    Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => {
+                        // Kernels older than 5.12 can only remount single mounts as read
+                        // only. Therefore, submounts are not made available.
+                        check(unsafe { libc::umount2(dest.as_ptr(), libc::MNT_DETACH) })?;
+                        mount(Some(src.as_c_str()), dest, None, libc::MS_BIND)?;
+                        remount_ro(dest)
+                    }
+                    result => result,
+                }
+            }
+            Self::BindRw { src, dest } => mount(
+                Some(src.as_c_str()),
+                dest,
+                None,
+                libc::MS_BIND | libc::MS_REC,
+            ),
+            Self::Tmpfs(path) => mount(
+                Some(cstr(b"tmpfs\0")),
+                path,
+                Some(cstr(b"tmpfs\0")),
+                libc::MS_NOSUID | libc::MS_NODEV,
+            ),
+            Self::Symlink { target, link } => {
+                check(unsafe { libc::symlink(target.as_ptr(), link.as_ptr()) })
+            }
+        }
+    }
+}
+
+/// Make bind mount and all its submounts read only
+///
+/// Other flags of the mounts are not changed.
+fn set_ro_recursive(path: &CStr) -> io::Result<()> {
+    let attr = MountAttr {
+        attr_set: MOUNT_ATTR_RDONLY | MOUNT_ATTR_NOSUID,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: 0,
+    };
+
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            libc::AT_RECURSIVE,
+            &attr as *const MountAttr,
+            std::mem::size_of::<MountAttr>(),
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Remount bind mount as read only
+///
+/// Flags like `nodev` of the original mount are locked inside a user
+/// namespace and have to be kept.
+fn remount_ro(path: &CStr) -> io::Result<()> {
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    check(unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) })?;
+    let stat = unsafe { stat.assume_init() };
+
+    let mut flags = libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | libc::MS_NOSUID;
+    for (st_flag, ms_flag) in [
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ] {
+        if stat.f_flag & st_flag != 0 {
+            flags |= ms_flag;
+        }
+    }
+
+    mount(None, path, None, flags)
+}
+
+/// Wait for the process in the PID namespace and exit with its status
+///
+/// Other fds are closed, such that the pipe `Command::spawn` uses to detect
+/// exec errors is only held by the child.
+fn forward_exit(pid: libc::pid_t) -> ! {
+    close_fds();
+
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } == pid {
+            break;
+        }
+
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            unsafe { libc::_exit(1) };
+        }
+    }
+
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+
+        // Terminate with the same signal, without dumping core
+        unsafe {
+            libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+            libc::signal(signal, libc::SIG_DFL);
+
+            let mut set = std::mem::MaybeUninit::<libc::sigset_t>::uninit();
+            libc::sigemptyset(set.as_mut_ptr());
+            libc::sigaddset(set.as_mut_ptr(), signal);
+            libc::sigprocmask(libc::SIG_UNBLOCK, set.as_ptr(), std::ptr::null_mut());
+
+            libc::raise(signal);
+            libc::_exit(signal.saturating_add(128));
+        }
+    }
+
+    unsafe { libc::_exit(libc::WEXITSTATUS(status)) }
+}
+
+/// Close all fds other than stdin, stdout, and stderr
+fn close_fds() {
+    let result = unsafe { libc::syscall(libc::SYS_close_range, 3_u32, u32::MAX, 0_u32) };
+    if result == 0 {
+        return;
+    }
+
+    // Fallback for kernels older than 5.9
+    let max_fd = match unsafe { libc::sysconf(libc::_SC_OPEN_MAX) } {
+        -1 => 1024,
+        n => libc::c_int::try_from(n.min(65536)).unwrap_or(1024),
+    };
+
+    for fd in 3..max_fd {
+        unsafe { libc::close(fd) };
+    }
+}
+
+fn mount(
+    src: Option<&CStr>,
+    target: &CStr,
+    fstype: Option<&CStr>,
+    flags: libc::c_ulong,
+) -> io::Result<()> {
+    check(unsafe {
+        libc::mount(
+            src.map_or(std::ptr::null(), CStr::as_ptr),
+            target.as_ptr(),
+            fstype.map_or(std::ptr::null(), CStr::as_ptr),
+            flags,
+            std::ptr::null(),
+        )
+    })
+}
+
+fn pivot_root(new_root: &CStr, put_old: &CStr) -> io::Result<()> {
+    let result =
+        unsafe { libc::syscall(libc::SYS_pivot_root, new_root.as_ptr(), put_old.as_ptr()) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn write_file(path: &CStr, content: &[u8]) -> io::Result<()> {
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+    check(fd)?;
+
+    let written = unsafe { libc::write(fd, content.as_ptr().cast(), content.len()) };
+    let write_error = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+
+    if usize::try_from(written).ok() != Some(content.len()) {
+        return Err(write_error);
+    }
+
+    Ok(())
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn cstr(bytes: &'static [u8]) -> &'static CStr {
+    CStr::from_bytes_with_nul(bytes).unwrap()
+}
+
+fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+fn new_root_path(path: &Path) -> io::Result<CString> {
+    prefixed(NEW_ROOT, path)
+}
+
+fn old_root_path(path: &Path) -> io::Result<CString> {
+    prefixed(OLD_ROOT, path)
+}
+
+fn prefixed(prefix: &str, path: &Path) -> io::Result<CString> {
+    let path = path.strip_prefix("/").unwrap_or(path);
+    cstring(&Path::new(prefix).join(path))
+}
+
+/// Returns `true` if user namespaces can be used to create sandboxes
+pub fn user_namespaces_usable() -> bool {
+    let mut command = std::process::Command::new("true");
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+
+    unsafe {
+        std::os::unix::process::CommandExt::pre_exec(&mut command, || {
+            check(libc::unshare(NAMESPACES))?;
+            // Fails if the namespace doesn't grant capabilities
+            mount(None, cstr(b"/\0"), None, libc::MS_SLAVE | libc::MS_REC)
+        });
+    }
+
+    command.status().is_ok_and(|status| status.success())
+}
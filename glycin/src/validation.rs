@@ -0,0 +1,214 @@
+// Copyright (c) 2024 GNOME Foundation Inc.
+
+//! Validation of data received from loaders
+//!
+//! Loaders are processing untrusted data and might be compromised. Therefore,
+//! every value they return is checked before it is used.
+
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+use glycin_utils::{BinaryData, Frame, ImageInfo};
+
+use crate::Error;
+
+/// Limits for data returned by loaders
+///
+/// Responses that exceed these limits fail with
+/// [`Error::InvalidLoaderResponse`].
+///
+/// ```
+/// # use glycin::ValidationLimits;
+/// let limits = ValidationLimits::new().max_exif_size(1024 * 1024);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ValidationLimits {
+    max_iccp_size: u64,
+    max_exif_size: u64,
+    max_xmp_size: u64,
+    max_text_len: usize,
+    max_delay: Duration,
+}
+
+impl Default for ValidationLimits {
+    fn default() -> Self {
+        Self {
+            max_iccp_size: 16 * 1024 * 1024,
+            max_exif_size: 16 * 1024 * 1024,
+            max_xmp_size: 16 * 1024 * 1024,
+            max_text_len: 1024,
+            max_delay: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl ValidationLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum size of ICC profiles in bytes
+    pub fn max_iccp_size(mut self, max_iccp_size: u64) -> Self {
+        self.max_iccp_size = max_iccp_size;
+        self
+    }
+
+    /// Maximum size of Exif data in bytes
+    pub fn max_exif_size(mut self, max_exif_size: u64) -> Self {
+        self.max_exif_size = max_exif_size;
+        self
+    }
+
+    /// Maximum size of XMP data in bytes
+    pub fn max_xmp_size(mut self, max_xmp_size: u64) -> Self {
+        self.max_xmp_size = max_xmp_size;
+        self
+    }
+
+    /// Maximum length of texts like the format name
+    pub fn max_text_len(mut self, max_text_len: usize) -> Self {
+        self.max_text_len = max_text_len;
+        self
+    }
+
+    /// Maximum duration to show a frame of an animation
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub(crate) fn validate_info(&self, info: &ImageInfo) -> Result<(), Error> {
+        let details = &info.details;
+
+        if let Some(exif) = &details.exif {
+            check_size("Exif data", exif, self.max_exif_size)?;
+        }
+
+        if let Some(xmp) = &details.xmp {
+            check_size("XMP data", xmp, self.max_xmp_size)?;
+        }
+
+        for text in [&details.format_name, &details.dimensions_text]
+            .into_iter()
+            .flatten()
+        {
+            if text.len() > self.max_text_len {
+                return Err(invalid(format!(
+                    "Text of {} bytes exceeds limit of {} bytes",
+                    text.len(),
+                    self.max_text_len
+                )));
+            }
+        }
+
+        if let Some((width, height)) = details.dimensions_inch {
+            if !(width.is_finite() && height.is_finite() && width > 0. && height > 0.) {
+                return Err(invalid(format!(
+                    "Invalid dimensions in inch: {width} x {height}"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check frame before its texture is used
+    ///
+    /// The frame can be scaled to `scale` if that was requested. Since
+    /// orientation transformations might not be reflected in the image info,
+    /// the frame can also have width and height swapped.
+    pub(crate) fn validate_frame(
+        &self,
+        frame: &Frame,
+        info: &ImageInfo,
+        scale: Option<(u32, u32)>,
+    ) -> Result<(), Error> {
+        if !fits_image((frame.width, frame.height), info, scale) {
+            return Err(invalid(format!(
+                "Frame of {}x{} is larger than the image of {}x{}",
+                frame.width, frame.height, info.width, info.height
+            )));
+        }
+
+        if let Some(delay) = *frame.delay {
+            if delay > self.max_delay {
+                return Err(invalid(format!(
+                    "Frame delay of {delay:?} exceeds limit of {:?}",
+                    self.max_delay
+                )));
+            }
+        }
+
+        if let Some(iccp) = &frame.details.iccp {
+            check_size("ICC profile", iccp, self.max_iccp_size)?;
+        }
+
+        if let Some(cicp) = &frame.details.cicp {
+            if cicp.len() != 4 {
+                return Err(invalid(format!(
+                    "CICP data has {} instead of 4 bytes",
+                    cicp.len()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns whether a frame of `size` fits into the image or requested scale
+fn fits_image(size: (u32, u32), info: &ImageInfo, scale: Option<(u32, u32)>) -> bool {
+    let (width, height) = size;
+
+    [Some((info.width, info.height)), scale]
+        .into_iter()
+        .flatten()
+        .any(|(max_width, max_height)| {
+            (width <= max_width && height <= max_height)
+                || (width <= max_height && height <= max_width)
+        })
+}
+
+fn check_size(name: &str, data: &BinaryData, max_size: u64) -> Result<(), Error> {
+    let stat = nix::sys::stat::fstat(data.as_raw_fd())
+        .map_err(|err| invalid(format!("Failed to stat {name}: {err}")))?;
+    let size = u64::try_from(stat.st_size).unwrap_or(u64::MAX);
+
+    if size > max_size {
+        return Err(invalid(format!(
+            "{name} of {size} bytes exceeds limit of {max_size} bytes"
+        )));
+    }
+
+    Ok(())
+}
+
+fn invalid(msg: String) -> Error {
+    Error::InvalidLoaderResponse(msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fits_image_dimensions() {
+        let info = ImageInfo::new(100, 10);
+
+        assert!(fits_image((100, 10), &info, None));
+        assert!(fits_image((50, 5), &info, None));
+        assert!(fits_image((10, 100), &info, None));
+        assert!(!fits_image((100, 100), &info, None));
+        assert!(!fits_image((101, 10), &info, None));
+        assert!(!fits_image((11, 100), &info, None));
+    }
+
+    #[test]
+    fn fits_image_scale() {
+        let info = ImageInfo::new(100, 10);
+
+        assert!(fits_image((200, 20), &info, Some((200, 20))));
+        assert!(fits_image((20, 200), &info, Some((200, 20))));
+        assert!(!fits_image((200, 200), &info, Some((200, 20))));
+    }
+}
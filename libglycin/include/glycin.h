@@ -59,6 +59,9 @@ G_DECLARE_FINAL_TYPE(GlyFrame, gly_frame, GLY, FRAME, GObject)
  * @GLY_SANDBOX_SELECTOR_BWRAP: bwrap
  * @GLY_SANDBOX_SELECTOR_FLATPAK_SPAWN: flatpak-spawn
  * @GLY_SANDBOX_SELECTOR_NOT_SANDBOXED: Disable sandbox. Unsafe, only use for testing and development.
+ * @GLY_SANDBOX_SELECTOR_LANDLOCK: Landlock and seccomp without namespaces. Since: 2.0
+ * @GLY_SANDBOX_SELECTOR_BUILTIN: Namespace sandbox like bwrap that doesn't require the bwrap binary. Since: 2.0
+ * @GLY_SANDBOX_SELECTOR_SECCOMP_ONLY: Only memory limit and seccomp filters without filesystem restrictions. Since: 2.0
  *
  * Sandbox mechanisms
  *
@@ -72,6 +75,9 @@ typedef enum
     GLY_SANDBOX_SELECTOR_BWRAP,
     GLY_SANDBOX_SELECTOR_FLATPAK_SPAWN,
     GLY_SANDBOX_SELECTOR_NOT_SANDBOXED,
+    GLY_SANDBOX_SELECTOR_LANDLOCK,
+    GLY_SANDBOX_SELECTOR_BUILTIN,
+    GLY_SANDBOX_SELECTOR_SECCOMP_ONLY,
 } GlySandboxSelector;
 
 GType gly_sandbox_selector_get_type(void);
@@ -239,6 +245,23 @@ uint32_t gly_image_get_width(GlyImage *image);
  */
 uint32_t gly_image_get_height(GlyImage *image);
 
+/**
+ * gly_image_get_progress:
+ * @image:
+ *
+ * Decoding progress of the current frame request between 0 and 1.
+ *
+ * Only loaders that support it report progress. Changes are notified via
+ * the `progress` property in the thread-default main context of the thread
+ * the image was loaded in. The progress is reset to 0 when a new frame is
+ * requested.
+ *
+ * Returns: Progress
+ *
+ * Since: 2.0
+ */
+double gly_image_get_progress(GlyImage *image);
+
 /**************** GlyFrame ****************/
 
 /**
@@ -273,6 +296,14 @@ int64_t gly_frame_get_delay(GlyFrame *frame);
  * GlyLoaderError:
  * @GLY_LOADER_ERROR_FAILED: Generic type for all other errors.
  * @GLY_LOADER_ERROR_UNKNOWN_IMAGE_FORMAT: Unknown image format.
+ * @GLY_LOADER_ERROR_CORRUPT: The image data is damaged or invalid. Since: 2.0
+ * @GLY_LOADER_ERROR_TRUNCATED: The image data ended unexpectedly. Since: 2.0
+ * @GLY_LOADER_ERROR_OUT_OF_MEMORY: Not enough memory was available. Since: 2.0
+ * @GLY_LOADER_ERROR_LIMIT_EXCEEDED: A limit on resources or dimensions was exceeded. Since: 2.0
+ * @GLY_LOADER_ERROR_SANDBOX_FAILURE: The sandbox could not be set up. Since: 2.0
+ * @GLY_LOADER_ERROR_CANCELLED: The operation was cancelled. Since: 2.0
+ * @GLY_LOADER_ERROR_LOADER_CRASHED: The loader process terminated unexpectedly. Since: 2.0
+ * @GLY_LOADER_ERROR_IO: Reading the image data failed. Since: 2.0
  *
  * Errors that can appear while loading images.
  *
@@ -282,6 +313,14 @@ typedef enum
 {
     GLY_LOADER_ERROR_FAILED,
     GLY_LOADER_ERROR_UNKNOWN_IMAGE_FORMAT,
+    GLY_LOADER_ERROR_CORRUPT,
+    GLY_LOADER_ERROR_TRUNCATED,
+    GLY_LOADER_ERROR_OUT_OF_MEMORY,
+    GLY_LOADER_ERROR_LIMIT_EXCEEDED,
+    GLY_LOADER_ERROR_SANDBOX_FAILURE,
+    GLY_LOADER_ERROR_CANCELLED,
+    GLY_LOADER_ERROR_LOADER_CRASHED,
+    GLY_LOADER_ERROR_IO,
 } GlyLoaderError;
 
 /**
@@ -11,14 +11,33 @@ use glib::translate::*;
 pub enum GlyLoaderError {
     Failed = 0,
     UnknownImageFormat = 1,
+    Corrupt = 2,
+    Truncated = 3,
+    OutOfMemory = 4,
+    LimitExceeded = 5,
+    SandboxFailure = 6,
+    Cancelled = 7,
+    LoaderCrashed = 8,
+    Io = 9,
 }
 
 impl From<&glycin::Error> for GlyLoaderError {
     fn from(value: &glycin::Error) -> Self {
         if value.unsupported_format().is_some() {
-            Self::UnknownImageFormat
-        } else {
-            Self::Failed
+            return Self::UnknownImageFormat;
+        }
+
+        match value.kind() {
+            glycin::ErrorKind::Corrupt => Self::Corrupt,
+            glycin::ErrorKind::Truncated => Self::Truncated,
+            glycin::ErrorKind::Unsupported => Self::UnknownImageFormat,
+            glycin::ErrorKind::OutOfMemory => Self::OutOfMemory,
+            glycin::ErrorKind::LimitExceeded => Self::LimitExceeded,
+            glycin::ErrorKind::SandboxFailure => Self::SandboxFailure,
+            glycin::ErrorKind::Cancelled => Self::Cancelled,
+            glycin::ErrorKind::LoaderCrashed => Self::LoaderCrashed,
+            glycin::ErrorKind::Io => Self::Io,
+            _ => Self::Failed,
         }
     }
 }
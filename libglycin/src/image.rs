@@ -109,3 +109,9 @@ pub unsafe extern "C" fn gly_image_get_height(image: *mut GlyImage) -> u32 {
     let image = gobject::GlyImage::from_glib_ptr_borrow(&(image as *const _));
     image.image_info().height
 }
+
+#[no_mangle]
+pub unsafe extern "C" fn gly_image_get_progress(image: *mut GlyImage) -> f64 {
+    let image = gobject::GlyImage::from_glib_ptr_borrow(&(image as *const _));
+    image.progress()
+}
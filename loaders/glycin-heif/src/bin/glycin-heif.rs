@@ -3,7 +3,9 @@ use std::io::{Cursor, Read};
 use std::sync::Mutex;
 
 use glycin_utils::*;
-use libheif_rs::{ColorProfile, ColorSpace, HeifContext, LibHeif, RgbChroma, StreamReader};
+use libheif_rs::{
+    ColorProfile, ColorSpace, DecodingOptions, HeifContext, LibHeif, RgbChroma, StreamReader,
+};
 
 init_main!(ImgDecoder::default());
 
@@ -11,19 +13,20 @@ init_main!(ImgDecoder::default());
 pub struct ImgDecoder {
     pub decoder: Mutex<Option<HeifContext<'static>>>,
     pub mime_type: OnceCell<String>,
+    pub accept_partial: OnceCell<bool>,
 }
 
 impl LoaderImplementation for ImgDecoder {
     fn init(
         &self,
-        mut stream: UnixStream,
+        input: Input,
         mime_type: String,
-        _details: InitializationDetails,
+        details: InitializationDetails,
     ) -> Result<ImageInfo, LoaderError> {
-        let mut data = Vec::new();
-        let total_size = stream.read_to_end(&mut data).internal_error()?;
+        let data = input.into_data().internal_error()?;
+        let total_size = data.len();
 
-        let stream_reader = StreamReader::new(Cursor::new(data), total_size.try_u64()?);
+        let stream_reader = StreamReader::new(data, total_size.try_u64()?);
         let context = HeifContext::read_from_reader(Box::new(stream_reader)).loading_error()?;
 
         let handle = context.primary_image_handle().loading_error()?;
@@ -46,16 +49,27 @@ impl LoaderImplementation for ImgDecoder {
 
         *self.decoder.lock().unwrap() = Some(context);
         let _ = self.mime_type.set(mime_type);
+        let _ = self
+            .accept_partial
+            .set(details.accept_partial.unwrap_or_default());
         Ok(image_info)
     }
 
     fn frame(&self, _frame_request: FrameRequest) -> Result<Frame, LoaderError> {
         let context = std::mem::take(&mut *self.decoder.lock().unwrap()).loading_error()?;
-        decode(context, self.mime_type.get().unwrap())
+        decode(
+            context,
+            self.mime_type.get().unwrap(),
+            self.accept_partial.get().copied().unwrap_or_default(),
+        )
     }
 }
 
-fn decode(context: HeifContext, mime_type: &str) -> Result<Frame, LoaderError> {
+fn decode(
+    context: HeifContext,
+    mime_type: &str,
+    accept_partial: bool,
+) -> Result<Frame, LoaderError> {
     let handle = context.primary_image_handle().loading_error()?;
 
     let rgb_chroma = if handle.luma_bits_per_pixel() > 8 {
@@ -85,7 +99,27 @@ fn decode(context: HeifContext, mime_type: &str) -> Result<Frame, LoaderError> {
     };
 
     let libheif = LibHeif::new();
-    let image_result = libheif.decode(&handle, ColorSpace::Rgb(rgb_chroma), None);
+
+    // Decode strictly first to know if the result would be incomplete. Files
+    // that fail strict decoding are decoded a second time without it.
+    let options = accept_partial
+        .then(|| {
+            let mut options = DecodingOptions::new()?;
+            options.set_strict_decoding(true);
+            Some(options)
+        })
+        .flatten();
+    let mut image_result = libheif.decode(&handle, ColorSpace::Rgb(rgb_chroma), options);
+
+    let mut partial_error = None;
+    if accept_partial {
+        if let Err(err) = &image_result {
+            if !matches!(err.sub_code, libheif_rs::HeifErrorSubCode::UnsupportedCodec) {
+                partial_error = Some(err.to_string());
+                image_result = libheif.decode(&handle, ColorSpace::Rgb(rgb_chroma), None);
+            }
+        }
+    }
 
     let mut image = match image_result {
         Err(err) if matches!(err.sub_code, libheif_rs::HeifErrorSubCode::UnsupportedCodec) => {
@@ -162,6 +196,11 @@ fn decode(context: HeifContext, mime_type: &str) -> Result<Frame, LoaderError> {
     }
     frame.details.alpha_channel = Some(handle.has_alpha_channel());
 
+    if let Some(error) = partial_error {
+        frame.details.incomplete = Some(true);
+        frame.details.error = Some(error);
+    }
+
     Ok(frame)
 }
 
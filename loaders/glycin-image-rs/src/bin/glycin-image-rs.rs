@@ -1,8 +1,10 @@
 #![allow(clippy::large_enum_variant)]
 
 use std::io::{Cursor, Read};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use glycin_utils::image_rs::Handler;
 use glycin_utils::*;
@@ -11,14 +13,51 @@ use image::{codecs, AnimationDecoder, ImageDecoder, ImageResult};
 
 init_main!(ImgDecoder::default());
 
-type Reader = Cursor<Vec<u8>>;
+/// Reports progress based on how much of the file the decoder has read
+type Reader = ProgressReader<InputData>;
 type FrameReceiver = Receiver<Result<Frame, LoaderError>>;
 type FrameSender = Sender<Result<Frame, LoaderError>>;
+type PreviewSender = SyncSender<Result<Frame, LoaderError>>;
+
+/// Interval in which cancellation is checked while waiting for a preview
+const CANCELLATION_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Start, step, and size of the pixel blocks of the Adam7 passes in x and y
+/// direction
+const ADAM7_PASSES: [((u32, u32, u32), (u32, u32, u32)); 7] = [
+    ((0, 8, 8), (0, 8, 8)),
+    ((4, 8, 4), (0, 8, 8)),
+    ((0, 4, 4), (4, 8, 4)),
+    ((2, 4, 2), (0, 4, 4)),
+    ((0, 2, 2), (2, 4, 2)),
+    ((1, 2, 1), (0, 2, 2)),
+    ((0, 1, 1), (1, 2, 1)),
+];
 
 #[derive(Default)]
 pub struct ImgDecoder {
     pub format: Mutex<Option<ImageRsFormat<Reader>>>,
     pub thread: Mutex<Option<(std::thread::JoinHandle<()>, FrameReceiver)>>,
+    /// Image data for decoding previews of interlaced PNGs
+    pub preview_data: Mutex<Option<InputData>>,
+    pub previews: Mutex<Option<Previews>>,
+}
+
+/// Thread decoding previews of interlaced PNGs
+pub struct Previews {
+    thread: std::thread::JoinHandle<()>,
+    recv: FrameReceiver,
+    stop: Arc<AtomicBool>,
+}
+
+impl Previews {
+    /// Stop the thread and wait until it freed its memory
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Unblocks the thread if it's waiting for the next preview request
+        drop(self.recv);
+        let _ = self.thread.join();
+    }
 }
 
 fn animated_worker(
@@ -142,21 +181,26 @@ pub fn animated_get_frame(
 impl LoaderImplementation for ImgDecoder {
     fn init(
         &self,
-        mut stream: UnixStream,
+        input: Input,
         mime_type: String,
-        _details: InitializationDetails,
+        details: InitializationDetails,
     ) -> Result<ImageInfo, LoaderError> {
-        let mut buf = Vec::new();
-        stream.read_to_end(&mut buf).internal_error()?;
-        let data = Cursor::new(buf);
+        let mut input_data = input.into_data().internal_error()?;
+        let data = ProgressReader::new(input_data.clone()).internal_error()?;
 
-        let mut format = ImageRsFormat::create(data.clone(), &mime_type)?;
+        let mut format = ImageRsFormat::create(data.clone(), &mime_type)?
+            .accept_partial(details.accept_partial.unwrap_or_default());
         if let Err(err) = format.set_no_limits() {
             eprint!("Failed to unset decoder limits: {err}");
         }
         let mut image_info = format.info();
+        details.check_dimensions(image_info.width, image_info.height)?;
 
-        let exif = exif::Reader::new().read_from_container(&mut data.clone());
+        if mime_type == "image/jpeg" && details.accept_partial == Some(true) {
+            format.truncated = !jpeg_complete(input_data.as_ref());
+        }
+
+        let exif = exif::Reader::new().read_from_container(&mut input_data);
         image_info.details.exif = exif
             .ok()
             .map(|x| BinaryData::from_data(x.buf()))
@@ -168,13 +212,33 @@ impl LoaderImplementation for ImgDecoder {
             let thead = std::thread::spawn(move || animated_worker(format, data, mime_type, send));
             *self.thread.lock().unwrap() = Some((thead, recv));
         } else {
+            if mime_type == "image/png" {
+                *self.preview_data.lock().unwrap() = Some(input_data);
+            }
             *self.format.lock().unwrap() = Some(format);
         }
 
         Ok(image_info)
     }
 
-    fn frame(&self, _frame_request: FrameRequest) -> Result<Frame, LoaderError> {
+    fn frame(&self, frame_request: FrameRequest) -> Result<Frame, LoaderError> {
+        // image-rs only decodes complete frames, such that cancellation can
+        // only be checked before decoding
+        frame_request.cancellation().check()?;
+
+        if frame_request.progressive == Some(true) {
+            if let Some(preview) = self.next_preview(&frame_request)? {
+                return Ok(preview);
+            }
+        }
+
+        // Stop decoding previews that were not requested anymore before
+        // decoding the final frame
+        if let Some(previews) = self.previews.lock().unwrap().take() {
+            previews.stop();
+        }
+        self.preview_data.lock().unwrap().take();
+
         let frame = if let Some(decoder) = std::mem::take(&mut *self.format.lock().unwrap()) {
             decoder.frame().loading_error()?
         } else if let Some((ref thread, ref recv)) = *self.thread.lock().unwrap() {
@@ -188,6 +252,177 @@ impl LoaderImplementation for ImgDecoder {
     }
 }
 
+impl ImgDecoder {
+    /// Returns the next preview or `None` if all previews have been returned
+    fn next_preview(&self, frame_request: &FrameRequest) -> Result<Option<Frame>, LoaderError> {
+        let mut previews = self.previews.lock().unwrap();
+
+        if previews.is_none() {
+            let Some(data) = self.preview_data.lock().unwrap().take() else {
+                return Ok(None);
+            };
+
+            let (send, recv) = sync_channel(0);
+            let stop = Arc::new(AtomicBool::new(false));
+            let stop_worker = stop.clone();
+            let thread =
+                std::thread::spawn(move || png_preview_worker(data.as_ref(), send, &stop_worker));
+            *previews = Some(Previews { thread, recv, stop });
+        }
+
+        let Some(Previews { recv, .. }) = previews.as_ref() else {
+            return Ok(None);
+        };
+
+        let cancellation = frame_request.cancellation();
+        loop {
+            match recv.recv_timeout(CANCELLATION_INTERVAL) {
+                Ok(preview) => return preview.map(Some),
+                Err(RecvTimeoutError::Timeout) => cancellation.check()?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Decodes previews for the Adam7 passes of interlaced PNGs
+///
+/// Pixels of a pass are repeated over the area that later passes fill in. The
+/// last pass is not decoded since the final image is decoded via image-rs. The
+/// channel is closed once all previews are sent. Decoding ends early once
+/// `stop` is set.
+fn png_preview_worker(data: &[u8], send: PreviewSender, stop: &AtomicBool) {
+    if let Err(err) = decode_png_previews(data, &send, stop) {
+        let _ = send.send(Err(err));
+    }
+}
+
+fn decode_png_previews(
+    data: &[u8],
+    send: &PreviewSender,
+    stop: &AtomicBool,
+) -> Result<(), LoaderError> {
+    let mut decoder = png::Decoder::new_with_limits(data, png::Limits { bytes: usize::MAX });
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().loading_error()?;
+
+    let info = reader.info();
+    if !info.interlaced {
+        return Ok(());
+    }
+
+    let width = info.width;
+    let height = info.height;
+    let iccp = info.icc_profile.as_ref().map(|x| x.to_vec());
+
+    let memory_format = match reader.output_color_type().0 {
+        png::ColorType::Grayscale => MemoryFormat::G8,
+        png::ColorType::GrayscaleAlpha => MemoryFormat::G8a8,
+        png::ColorType::Rgb => MemoryFormat::R8g8b8,
+        png::ColorType::Rgba => MemoryFormat::R8g8b8a8,
+        color_type => {
+            return Err(LoaderError::loading(&format!(
+                "Unexpected color type: {color_type:?}"
+            )))
+        }
+    };
+    let n_bytes = memory_format.n_bytes().usize();
+    let stride = width as usize * n_bytes;
+
+    // Passes without pixels are skipped by the decoder
+    let passes = ADAM7_PASSES
+        .into_iter()
+        .filter(|((x0, _, _), (y0, _, _))| *x0 < width && *y0 < height)
+        .collect::<Vec<_>>();
+
+    let mut canvas = vec![0; stride * height as usize];
+
+    for ((x0, dx, bw), (y0, dy, bh)) in passes.iter().take(passes.len().saturating_sub(1)) {
+        for y in (*y0..height).step_by(*dy as usize) {
+            if stop.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            let row = reader
+                .next_interlaced_row()
+                .loading_error()?
+                .loading_error()?;
+            let y_end = (y + bh).min(height);
+
+            for (pixel, x) in row
+                .data()
+                .chunks_exact(n_bytes)
+                .zip((*x0..width).step_by(*dx as usize))
+            {
+                let x_end = (x + bw).min(width);
+                for block_y in y..y_end {
+                    let offset = block_y as usize * stride;
+                    canvas[offset + x as usize * n_bytes..offset + x_end as usize * n_bytes]
+                        .chunks_exact_mut(n_bytes)
+                        .for_each(|target| target.copy_from_slice(pixel));
+                }
+            }
+        }
+
+        let texture = BinaryData::from_data(&canvas)?;
+
+        let mut frame = Frame::new(width, height, memory_format, texture).loading_error()?;
+        frame.details.iccp = iccp
+            .as_deref()
+            .map(BinaryData::from_data)
+            .transpose()
+            .loading_error()?;
+        frame.details.incomplete = Some(true);
+
+        // Stop decoding if glycin is no longer interested in previews
+        if send.send(Ok(frame)).is_err() {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if the JPEG data contains the end of image marker
+///
+/// zune-jpeg fills in the missing part of truncated files without reporting an
+/// error, such that truncation has to be detected separately.
+fn jpeg_complete(data: &[u8]) -> bool {
+    // Skip start of image marker
+    let mut pos = 2;
+
+    while pos < data.len() {
+        if data[pos] != 0xFF {
+            // Entropy coded data
+            pos += 1;
+            continue;
+        }
+
+        let Some(&marker) = data.get(pos + 1) else {
+            return false;
+        };
+
+        match marker {
+            // End of image
+            0xD9 => return true,
+            // Fill bytes
+            0xFF => pos += 1,
+            // Escaped 0xFF in entropy coded data, restart markers, and other
+            // markers without a segment
+            0x00 | 0x01 | 0xD0..=0xD8 => pos += 2,
+            // Skip the segment, which includes the length field itself
+            _ => {
+                let Some(length) = data.get(pos + 2..pos + 4) else {
+                    return false;
+                };
+                pos += 2 + usize::from(u16::from_be_bytes([length[0], length[1]]));
+            }
+        }
+    }
+
+    false
+}
+
 pub enum ImageRsDecoder<T: std::io::BufRead + std::io::Seek> {
     Bmp(codecs::bmp::BmpDecoder<T>),
     Dds(codecs::dds::DdsDecoder<T>),
@@ -207,6 +442,8 @@ pub enum ImageRsDecoder<T: std::io::BufRead + std::io::Seek> {
 pub struct ImageRsFormat<T: std::io::BufRead + std::io::Seek> {
     decoder: ImageRsDecoder<T>,
     handler: Handler,
+    /// The data ended before the image, but the decoder doesn't report it
+    truncated: bool,
 }
 
 impl ImageRsFormat<Reader> {
@@ -254,6 +491,7 @@ impl ImageRsFormat<Reader> {
             .format_name("PNG")
             .supports_two_alpha_modes(true)
             .supports_two_grayscale_modes(true)
+            .supports_partial_results(true)
             .default_bit_depth(8),
             "image/x-portable-bitmap" => Self::new(ImageRsDecoder::Pnm(
                 codecs::pnm::PnmDecoder::new(data).loading_error()?,
@@ -325,10 +563,23 @@ impl<'a, T: std::io::BufRead + std::io::Seek + 'a> ImageRsFormat<T> {
         self
     }
 
+    pub fn supports_partial_results(mut self, supports_partial_results: bool) -> Self {
+        self.handler = self
+            .handler
+            .supports_partial_results(supports_partial_results);
+        self
+    }
+
+    pub fn accept_partial(mut self, accept_partial: bool) -> Self {
+        self.handler = self.handler.accept_partial(accept_partial);
+        self
+    }
+
     fn new(decoder: ImageRsDecoder<T>) -> Self {
         Self {
             decoder,
             handler: Handler::default(),
+            truncated: false,
         }
     }
 
@@ -351,7 +602,7 @@ impl<'a, T: std::io::BufRead + std::io::Seek + 'a> ImageRsFormat<T> {
     }
 
     fn frame(self) -> Result<Frame, LoaderError> {
-        match self.decoder {
+        let mut frame = match self.decoder {
             ImageRsDecoder::Bmp(d) => self.handler.frame(d),
             ImageRsDecoder::Dds(d) => self.handler.frame(d),
             ImageRsDecoder::Farbfeld(d) => self.handler.frame(d),
@@ -365,7 +616,14 @@ impl<'a, T: std::io::BufRead + std::io::Seek + 'a> ImageRsFormat<T> {
             ImageRsDecoder::Tga(d) => self.handler.frame(d),
             ImageRsDecoder::Tiff(d) => self.handler.frame(d),
             ImageRsDecoder::WebP(d) => self.handler.frame(d),
+        }?;
+
+        if self.truncated {
+            frame.details.incomplete = Some(true);
+            frame.details.error = Some(String::from("Image data ended unexpectedly"));
         }
+
+        Ok(frame)
     }
 
     fn frame_details(&mut self) -> Result<FrameDetails, LoaderError> {
@@ -1,34 +1,59 @@
 #![allow(clippy::large_enum_variant)]
 
-use std::io::{Cursor, Read, Write};
+use std::io::{Cursor, Write};
 use std::mem::MaybeUninit;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use glycin_utils::*;
 use jpegxl_rs::image::ToDynamic;
 use jpegxl_sys::codestream_header::*;
 use jpegxl_sys::decode::*;
-use jpegxl_sys::types::{JxlBool, JxlBoxType};
+use jpegxl_sys::types::{JxlBool, JxlBoxType, JxlDataType, JxlEndianness, JxlPixelFormat};
 
 init_main!(ImgDecoder::default());
 
-type InitData = Option<(Vec<u8>, Option<Vec<u8>>)>;
+type InitData = Option<(InputData, Option<Vec<u8>>)>;
+type PreviewReceiver = Receiver<Result<Frame, LoaderError>>;
+type PreviewSender = SyncSender<Result<Frame, LoaderError>>;
+
+/// Interval in which cancellation is checked while waiting for a preview
+const CANCELLATION_INTERVAL: Duration = Duration::from_millis(50);
 
 #[derive(Default)]
 pub struct ImgDecoder {
     pub decoder: Mutex<InitData>,
+    pub previews: Mutex<Option<Previews>>,
+}
+
+/// Thread decoding previews of progressive images
+pub struct Previews {
+    thread: std::thread::JoinHandle<()>,
+    recv: PreviewReceiver,
+    stop: Arc<AtomicBool>,
+}
+
+impl Previews {
+    /// Stop the thread and wait until it freed its memory
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Unblocks the thread if it's waiting for the next preview request
+        drop(self.recv);
+        let _ = self.thread.join();
+    }
 }
 
 impl LoaderImplementation for ImgDecoder {
     fn init(
         &self,
-        mut stream: UnixStream,
+        input: Input,
         _mime_type: String,
         _details: InitializationDetails,
     ) -> Result<ImageInfo, LoaderError> {
-        let mut data = Vec::new();
-        stream.read_to_end(&mut data).loading_error()?;
-        let (info, iccp, exif) = basic_info(&data);
+        let data = input.into_data().loading_error()?;
+        let (info, iccp, exif) = basic_info(data.as_ref());
 
         let info = info.loading_error()?;
 
@@ -45,15 +70,26 @@ impl LoaderImplementation for ImgDecoder {
         Ok(image_info)
     }
 
-    fn frame(&self, _frame_request: FrameRequest) -> Result<Frame, LoaderError> {
+    fn frame(&self, frame_request: FrameRequest) -> Result<Frame, LoaderError> {
+        if frame_request.progressive == Some(true) {
+            if let Some(preview) = self.next_preview(&frame_request)? {
+                return Ok(preview);
+            }
+        }
+
         let (data, iccp) = std::mem::take(&mut *self.decoder.lock().unwrap()).loading_error()?;
+        // Stop decoding previews that were not requested anymore before
+        // decoding the final frame
+        if let Some(previews) = self.previews.lock().unwrap().take() {
+            previews.stop();
+        }
 
         let decoder = jpegxl_rs::decode::decoder_builder()
             .build()
             .loading_error()?;
 
         let image = decoder
-            .decode_to_image(&data)
+            .decode_to_image(data.as_ref())
             .loading_error()?
             .loading_error()?;
 
@@ -94,6 +130,161 @@ impl LoaderImplementation for ImgDecoder {
     }
 }
 
+impl ImgDecoder {
+    /// Returns the next preview or `None` if all previews have been returned
+    fn next_preview(&self, frame_request: &FrameRequest) -> Result<Option<Frame>, LoaderError> {
+        let mut previews = self.previews.lock().unwrap();
+
+        if previews.is_none() {
+            let Some((data, iccp)) = self.decoder.lock().unwrap().clone() else {
+                return Ok(None);
+            };
+
+            let (send, recv) = sync_channel(0);
+            let stop = Arc::new(AtomicBool::new(false));
+            let stop_worker = stop.clone();
+            let thread =
+                std::thread::spawn(move || preview_worker(data.as_ref(), iccp, send, &stop_worker));
+            *previews = Some(Previews { thread, recv, stop });
+        }
+
+        let Some(Previews { recv, .. }) = previews.as_ref() else {
+            return Ok(None);
+        };
+
+        let cancellation = frame_request.cancellation();
+        loop {
+            match recv.recv_timeout(CANCELLATION_INTERVAL) {
+                Ok(preview) => return preview.map(Some),
+                Err(RecvTimeoutError::Timeout) => cancellation.check()?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Decodes previews for the passes of progressive images
+///
+/// The final image is not sent, since it is decoded via jpegxl-rs with the
+/// image's bit depth. The channel is closed once all previews are sent.
+/// Decoding ends after the current pass once `stop` is set.
+fn preview_worker(data: &[u8], iccp: Option<Vec<u8>>, send: PreviewSender, stop: &AtomicBool) {
+    unsafe {
+        let decoder = JxlDecoderCreate(std::ptr::null());
+
+        if let Err(err) = decode_previews(decoder, data, iccp, &send, stop) {
+            let _ = send.send(Err(err));
+        }
+
+        JxlDecoderDestroy(decoder);
+    }
+}
+
+unsafe fn decode_previews(
+    decoder: *mut JxlDecoder,
+    data: &[u8],
+    iccp: Option<Vec<u8>>,
+    send: &PreviewSender,
+    stop: &AtomicBool,
+) -> Result<(), LoaderError> {
+    JxlDecoderSubscribeEvents(
+        decoder,
+        JxlDecoderStatus::BasicInfo as i32
+            | JxlDecoderStatus::FrameProgression as i32
+            | JxlDecoderStatus::FullImage as i32,
+    );
+    JxlDecoderSetProgressiveDetail(decoder, JxlProgressiveDetail::Passes);
+    JxlDecoderSetInput(decoder, data.as_ptr(), data.len());
+    JxlDecoderCloseInput(decoder);
+
+    let mut info = MaybeUninit::uninit();
+    let mut format = None;
+    let mut buf = Vec::new();
+
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        match JxlDecoderProcessInput(decoder) {
+            JxlDecoderStatus::BasicInfo => {
+                if JxlDecoderGetBasicInfo(decoder, info.as_mut_ptr()) != JxlDecoderStatus::Success {
+                    return Err(LoaderError::loading(&"Failed to read basic info"));
+                }
+                let info = info.assume_init_ref();
+
+                let alpha_channel = info.alpha_bits > 0;
+                let memory_format = match (info.num_color_channels, alpha_channel) {
+                    (1, false) => MemoryFormat::G8,
+                    (1, true) => MemoryFormat::G8a8,
+                    (_, false) => MemoryFormat::R8g8b8,
+                    (_, true) => MemoryFormat::R8g8b8a8,
+                };
+
+                format = Some((
+                    JxlPixelFormat {
+                        num_channels: memory_format.n_bytes().u32(),
+                        data_type: JxlDataType::Uint8,
+                        endianness: JxlEndianness::Native,
+                        align: 0,
+                    },
+                    memory_format,
+                ));
+            }
+            JxlDecoderStatus::NeedImageOutBuffer => {
+                let (pixel_format, _) = format.as_ref().loading_error()?;
+
+                let mut size = 0;
+                if JxlDecoderImageOutBufferSize(decoder, pixel_format, &mut size)
+                    != JxlDecoderStatus::Success
+                {
+                    return Err(LoaderError::loading(&"Failed to get output buffer size"));
+                }
+
+                buf.resize(size, 0);
+                JxlDecoderSetImageOutBuffer(decoder, pixel_format, buf.as_mut_ptr().cast(), size);
+            }
+            JxlDecoderStatus::FrameProgression => {
+                if JxlDecoderFlushImage(decoder) != JxlDecoderStatus::Success {
+                    continue;
+                }
+
+                let memory_format = format.as_ref().map(|(_, x)| *x).loading_error()?;
+                let info = info.assume_init_ref();
+
+                // Dimensions after applying the orientation
+                let (width, height) = if info.orientation as u32 > 4 {
+                    (info.ysize, info.xsize)
+                } else {
+                    (info.xsize, info.ysize)
+                };
+
+                let texture = BinaryData::from_data(&buf)?;
+
+                let mut frame =
+                    Frame::new(width, height, memory_format, texture).loading_error()?;
+                frame.details.iccp = iccp
+                    .as_deref()
+                    .map(BinaryData::from_data)
+                    .transpose()
+                    .loading_error()?;
+                frame.details.incomplete = Some(true);
+
+                // Stop decoding if glycin is no longer interested in previews
+                if send.send(Ok(frame)).is_err() {
+                    return Ok(());
+                }
+            }
+            JxlDecoderStatus::FullImage | JxlDecoderStatus::Success => return Ok(()),
+            status => {
+                return Err(LoaderError::loading(&format!(
+                    "Unexpected status while decoding previews: {status:?}"
+                )))
+            }
+        }
+    }
+}
+
 fn basic_info(data: &[u8]) -> (Option<JxlBasicInfo>, Option<Vec<u8>>, Option<Vec<u8>>) {
     unsafe {
         let decoder = JxlDecoderCreate(std::ptr::null());
@@ -1,14 +1,41 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::{Cursor, Read};
+use std::os::fd::OwnedFd;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Mutex;
 
+use gio::glib;
 use gio::prelude::*;
 use glycin_utils::*;
+use quick_xml::escape::escape;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesCData, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
 
 /// Current librsvg limit on maximum dimensions. See
 /// <https://gitlab.gnome.org/GNOME/librsvg/-/issues/938>
 pub const RSVG_MAX_SIZE: u32 = 32_767;
 
+/// Number of rows rendered at once before checking for cancellation
+const STRIP_HEIGHT: i32 = 512;
+
+/// Elements for which librsvg loads the file referenced via `href`
+const HREF_ELEMENTS: &[&[u8]] = &[
+    b"feImage",
+    b"filter",
+    b"image",
+    b"linearGradient",
+    b"pattern",
+    b"radialGradient",
+    b"textPath",
+    b"use",
+];
+/// Processing instruction that references external CSS
+const STYLESHEET_PI: &[u8] = b"xml-stylesheet";
+/// Maximum total size of files embedded into a document
+const MAX_EMBEDDED_SIZE: usize = 128 * 1024 * 1024;
+
 init_main!(ImgDecoder::default());
 
 #[derive(Default)]
@@ -25,20 +52,34 @@ pub struct ImgDecoderDetails {
 pub struct Instruction {
     total_size: (u32, u32),
     area: cairo::Rectangle,
+    cancellation: Cancellation,
+    /// Whether glycin can cancel the request
+    cancellable: bool,
 }
 
 pub fn thread(
-    stream: UnixStream,
+    input: Input,
     base_file: Option<gio::File>,
     info_send: Sender<Result<ImageInfo, LoaderError>>,
     frame_send: Sender<Result<Frame, LoaderError>>,
     instr_recv: Receiver<Instruction>,
 ) {
-    let input_stream = unsafe { gio::UnixInputStream::take_fd(stream) };
+    let input_stream = unsafe { gio::UnixInputStream::take_fd(OwnedFd::from(input)) };
 
-    let handle = rsvg::Loader::new()
-        .read_stream(&input_stream, base_file.as_ref(), gio::Cancellable::NONE)
-        .loading_error();
+    let handle = if base_file.is_some() {
+        rsvg::Loader::new()
+            .read_stream(&input_stream, base_file.as_ref(), gio::Cancellable::NONE)
+            .loading_error()
+    } else {
+        // Without access to the image's directory, resources have to be requested
+        read_svg(input_stream).and_then(|svg| {
+            let input_stream =
+                gio::MemoryInputStream::from_bytes(&glib::Bytes::from_owned(embed_resources(svg)));
+            rsvg::Loader::new()
+                .read_stream(&input_stream, gio::File::NONE, gio::Cancellable::NONE)
+                .loading_error()
+        })
+    };
 
     let handle = match handle {
         Ok(handle) => handle,
@@ -74,6 +115,243 @@ pub fn thread(
     }
 }
 
+/// Read SVG and decompress it if necessary
+fn read_svg(input_stream: gio::UnixInputStream) -> Result<Vec<u8>, LoaderError> {
+    let mut data = Vec::new();
+    input_stream
+        .into_read()
+        .read_to_end(&mut data)
+        .loading_error()?;
+
+    // Gzip magic number
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return Ok(data);
+    }
+
+    let compressed = gio::MemoryInputStream::from_bytes(&glib::Bytes::from_owned(data));
+    let decompressor = gio::ZlibDecompressor::new(gio::ZlibCompressorFormat::Gzip);
+
+    let mut svg = Vec::new();
+    gio::ConverterInputStream::new(&compressed, &decompressor)
+        .into_read()
+        .read_to_end(&mut svg)
+        .loading_error()?;
+
+    Ok(svg)
+}
+
+/// Replace relative references with data URLs of files provided by glycin
+///
+/// librsvg only resolves references relative to a local base file. Therefore,
+/// the referenced files are requested before the document is parsed. Only
+/// references that librsvg loads are considered, and each file is requested
+/// once.
+fn embed_resources(svg: Vec<u8>) -> Vec<u8> {
+    match ResourceEmbedder::default().embed(&svg) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("Failed to embed resources: {err}");
+            svg
+        }
+    }
+}
+
+#[derive(Default)]
+struct ResourceEmbedder {
+    /// Data URLs of already requested files, without fragment
+    data_urls: HashMap<String, Option<String>>,
+    /// Total size of the embedded files
+    embedded_size: usize,
+}
+
+impl ResourceEmbedder {
+    fn embed(&mut self, svg: &[u8]) -> quick_xml::Result<Vec<u8>> {
+        let mut reader = Reader::from_reader(svg);
+        let mut writer = Writer::new(Vec::with_capacity(svg.len()));
+        let mut in_style = false;
+
+        loop {
+            let event = match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(element) => {
+                    in_style = element.local_name().as_ref() == b"style";
+                    Event::Start(self.rewrite_element(&element))
+                }
+                Event::Empty(element) => Event::Empty(self.rewrite_element(&element)),
+                Event::End(element) => {
+                    in_style = false;
+                    Event::End(element)
+                }
+                Event::Text(text) if in_style => {
+                    let css = self.rewrite_css(&text.unescape()?);
+                    match css {
+                        Some(css) => Event::Text(BytesText::new(&css).into_owned()),
+                        None => Event::Text(text),
+                    }
+                }
+                Event::CData(cdata) if in_style => {
+                    let css = self.rewrite_css(&String::from_utf8_lossy(&cdata));
+                    match css {
+                        Some(css) => Event::CData(BytesCData::new(css)),
+                        None => Event::CData(cdata),
+                    }
+                }
+                Event::PI(pi) if pi.starts_with(STYLESHEET_PI) => {
+                    // Pseudo-attributes have the same syntax as attributes
+                    let rewritten = std::str::from_utf8(&pi).ok().map(|content| {
+                        let pi_element = BytesStart::from_content(content, STYLESHEET_PI.len());
+                        let rewritten = self.rewrite_attributes(&pi_element, true);
+                        String::from_utf8_lossy(&rewritten).into_owned()
+                    });
+
+                    match rewritten {
+                        Some(content) => Event::PI(BytesText::from_escaped(content)),
+                        None => Event::PI(pi),
+                    }
+                }
+                event => event,
+            };
+
+            writer.write_event(event)?;
+        }
+
+        Ok(writer.into_inner())
+    }
+
+    fn rewrite_element(&mut self, element: &BytesStart) -> BytesStart<'static> {
+        let loads_href = HREF_ELEMENTS.contains(&element.local_name().as_ref());
+        self.rewrite_attributes(element, loads_href)
+    }
+
+    /// Embed `href` if requested and files referenced via `url()`
+    fn rewrite_attributes(
+        &mut self,
+        element: &BytesStart,
+        embed_href: bool,
+    ) -> BytesStart<'static> {
+        let mut rewritten = element.to_owned();
+        rewritten.clear_attributes();
+
+        for attr in element.attributes().with_checks(false) {
+            let Ok(attr) = attr else {
+                // Leave malformed elements to librsvg
+                return element.to_owned();
+            };
+
+            let new_value = attr.unescape_value().ok().and_then(|value| {
+                if embed_href && attr.key.local_name().as_ref() == b"href" {
+                    self.data_url(&value)
+                } else {
+                    self.rewrite_css(&value)
+                }
+            });
+
+            match new_value {
+                Some(value) => rewritten.push_attribute(Attribute {
+                    key: attr.key,
+                    value: Cow::Owned(escape(&value).as_bytes().to_vec()),
+                }),
+                None => rewritten.push_attribute(attr),
+            }
+        }
+
+        rewritten
+    }
+
+    /// Replace `url()` references in CSS
+    ///
+    /// Returns `None` if nothing was replaced.
+    fn rewrite_css(&mut self, css: &str) -> Option<String> {
+        const URL: &str = "url(";
+
+        let mut result = String::with_capacity(css.len());
+        let mut rest = css;
+        let mut changed = false;
+
+        while let Some(start) = rest.find(URL) {
+            let (before, after) = rest.split_at(start + URL.len());
+            let Some(end) = after.find(')') else {
+                break;
+            };
+
+            let value = &after[..end];
+            let href = value.trim().trim_matches(|x| x == '"' || x == '\'');
+
+            result.push_str(before);
+            match self.data_url(href) {
+                Some(data_url) => {
+                    result.push('"');
+                    result.push_str(&data_url);
+                    result.push('"');
+                    changed = true;
+                }
+                None => result.push_str(value),
+            }
+            rest = &after[end..];
+        }
+
+        result.push_str(rest);
+        changed.then_some(result)
+    }
+
+    /// Request a relative reference and encode it as data URL
+    fn data_url(&mut self, href: &str) -> Option<String> {
+        let (path, fragment) = match href.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (href, None),
+        };
+
+        // Ignore references within the document, absolute paths, and URLs
+        if path.is_empty() || path.starts_with('/') || path.contains(':') {
+            return None;
+        }
+
+        let path = glib::Uri::unescape_string(path, None::<&str>)?.to_string();
+
+        let mut data_url = match self.data_urls.get(&path) {
+            Some(data_url) => data_url.clone()?,
+            None => {
+                let data_url = self.load(&path);
+                self.data_urls.insert(path, data_url.clone());
+                data_url?
+            }
+        };
+
+        if let Some(fragment) = fragment {
+            data_url.push('#');
+            data_url.push_str(fragment);
+        }
+
+        Some(data_url)
+    }
+
+    fn load(&mut self, path: &str) -> Option<String> {
+        let mut data = Vec::new();
+        if let Err(err) = request_resource(path).and_then(|mut file| {
+            file.read_to_end(&mut data).loading_error()?;
+            Ok(())
+        }) {
+            eprintln!("Failed to load resource: {err}");
+            return None;
+        }
+
+        let embedded_size = self.embedded_size.saturating_add(data.len());
+        if embedded_size > MAX_EMBEDDED_SIZE {
+            eprintln!("Not loading resource '{path}': Resources exceed {MAX_EMBEDDED_SIZE} bytes");
+            return None;
+        }
+        self.embedded_size = embedded_size;
+
+        let (content_type, _) = gio::content_type_guess(Some(path), &data);
+        let mime_type = gio::content_type_get_mime_type(&content_type)?;
+
+        Some(format!(
+            "data:{mime_type};base64,{}",
+            glib::base64_encode(&data)
+        ))
+    }
+}
+
 pub fn render(renderer: &rsvg::CairoRenderer, instr: Instruction) -> Result<Frame, LoaderError> {
     let area = instr.area;
     let (total_width, total_height) = instr.total_size;
@@ -86,18 +364,37 @@ pub fn render(renderer: &rsvg::CairoRenderer, instr: Instruction) -> Result<Fram
     .loading_error()?;
 
     let context = cairo::Context::new(&surface).loading_error()?;
+    let viewport = cairo::Rectangle::new(
+        -area.x(),
+        -area.y(),
+        total_width as f64,
+        total_height as f64,
+    );
+
+    // Render in strips to be able to stop if the request was cancelled and to
+    // report progress. Each strip traverses the whole document, so requests
+    // that can't be cancelled are rendered at once.
+    let surface_height = surface.height();
+    let strip_height = if instr.cancellable {
+        STRIP_HEIGHT
+    } else {
+        surface_height.max(1)
+    };
 
-    renderer
-        .render_document(
-            &context,
-            &cairo::Rectangle::new(
-                -area.x(),
-                -area.y(),
-                total_width as f64,
-                total_height as f64,
-            ),
-        )
-        .loading_error()?;
+    for y in (0..surface_height).step_by(strip_height as usize) {
+        instr.cancellation.check()?;
+
+        context.save().loading_error()?;
+        context.rectangle(0., y.into(), area.width(), strip_height.into());
+        context.clip();
+        renderer
+            .render_document(&context, &viewport)
+            .loading_error()?;
+        context.restore().loading_error()?;
+
+        let rows_done = (y + strip_height).min(surface_height);
+        report_progress_rows(rows_done as u32, surface_height as u32);
+    }
 
     drop(context);
 
@@ -127,7 +424,7 @@ pub fn render(renderer: &rsvg::CairoRenderer, instr: Instruction) -> Result<Fram
 impl LoaderImplementation for ImgDecoder {
     fn init(
         &self,
-        stream: UnixStream,
+        input: Input,
         _mime_type: String,
         details: InitializationDetails,
     ) -> Result<ImageInfo, LoaderError> {
@@ -140,7 +437,7 @@ impl LoaderImplementation for ImgDecoder {
             .as_ref()
             .map(|x| gio::File::for_path(x).child("placeholder.svg"));
 
-        std::thread::spawn(move || thread(stream, base_file, info_send, frame_send, instr_recv));
+        std::thread::spawn(move || thread(input, base_file, info_send, frame_send, instr_recv));
         let image_info = info_recv.recv().unwrap()?;
 
         *self.thread.lock().unwrap() = Some(ImgDecoderDetails {
@@ -167,7 +464,12 @@ impl LoaderImplementation for ImgDecoder {
             cairo::Rectangle::new(0., 0., total_size.0.into(), total_size.1.into())
         };
 
-        let instr = Instruction { total_size, area };
+        let instr = Instruction {
+            total_size,
+            area,
+            cancellation: frame_request.cancellation(),
+            cancellable: frame_request.cancel_token.is_some(),
+        };
 
         thread.instr_send.send(instr).unwrap();
 
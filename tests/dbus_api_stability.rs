@@ -1,4 +1,3 @@
-use std::os::unix::net::UnixStream;
 use std::sync::Mutex;
 
 const INTERFACE_NAME: &str = "org.gnome.glycin.Loader";
@@ -64,7 +63,7 @@ async fn start_dbus() {
     impl glycin_utils::LoaderImplementation for MockDecoder {
         fn init(
             &self,
-            _stream: UnixStream,
+            _input: glycin_utils::Input,
             _mime_type: String,
             _details: glycin_utils::InitializationDetails,
         ) -> Result<glycin_utils::ImageInfo, glycin_utils::LoaderError> {
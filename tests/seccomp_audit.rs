@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use glycin::SandboxMechanism;
+
+/// Check that loaders don't use syscalls that are not in their seccomp filter
+///
+/// Uses the builtin sandbox and is skipped if it's not usable on this system.
+#[test]
+fn seccomp_audit() {
+    async_global_executor::block_on(async {
+        let diagnostics = glycin::sandbox_diagnostics().await;
+        if !diagnostics.user_namespaces || !diagnostics.seccomp {
+            eprintln!("Skipping seccomp audit: User namespaces or seccomp are not available");
+            return;
+        }
+
+        let report =
+            std::env::temp_dir().join(format!("glycin-seccomp-audit-{}", std::process::id()));
+        if report.exists() {
+            std::fs::remove_file(&report).unwrap();
+        }
+        std::env::set_var("GLYCIN_SECCOMP_AUDIT", &report);
+        let _audit_env = AuditEnvGuard;
+
+        for dir in std::fs::read_dir("test-images/images").unwrap() {
+            // Reference images are stored next to the test directories
+            let dir = dir.unwrap().path();
+            if !dir.is_dir() {
+                continue;
+            }
+
+            for entry in std::fs::read_dir(dir).unwrap() {
+                load(&entry.unwrap().path()).await;
+            }
+        }
+
+        // The file is only created if a loader used syscalls that are not allowed
+        let used_syscalls = if report.exists() {
+            let used_syscalls = std::fs::read_to_string(&report).unwrap();
+            std::fs::remove_file(&report).unwrap();
+            used_syscalls
+        } else {
+            String::new()
+        };
+
+        assert!(used_syscalls.is_empty(), "{used_syscalls}");
+    });
+}
+
+/// Unsets `GLYCIN_SECCOMP_AUDIT` again, even if loading an image panics
+struct AuditEnvGuard;
+
+impl Drop for AuditEnvGuard {
+    fn drop(&mut self) {
+        std::env::remove_var("GLYCIN_SECCOMP_AUDIT");
+    }
+}
+
+async fn load(path: &Path) {
+    eprintln!("  - {path:?}");
+
+    let file = gio::File::for_path(path);
+    let mut loader = glycin::Loader::new(file);
+    loader.sandbox_mechanism(Some(SandboxMechanism::Builtin));
+    let image = loader.load().await.unwrap();
+    image.next_frame().await.unwrap();
+
+    // Returns once the loader exited and the audit results were reported
+    image.release().await.unwrap();
+}
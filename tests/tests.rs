@@ -1,5 +1,7 @@
 use std::ffi::OsString;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use gdk::prelude::*;
 
@@ -38,6 +40,107 @@ fn fonts() {
     test_dir("test-images/images/fonts");
 }
 
+/// Truncated PNGs return the rows that could be decoded
+#[test]
+fn partial_png() {
+    async_global_executor::block_on(async {
+        let reference_path = "test-images/images/color.png";
+        let reference = get_downloaded_texture(reference_path).await;
+
+        let data = std::fs::read(reference_path).unwrap();
+        let path = std::env::temp_dir().join(format!("glycin-partial-{}.png", std::process::id()));
+        std::fs::write(&path, &data[..data.len() / 2]).unwrap();
+
+        let mut loader = glycin::Loader::new(gio::File::for_path(&path));
+        loader.accept_partial(true);
+        let image = loader.load().await.unwrap();
+        let frame = image.next_frame().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(frame.details.incomplete, Some(true));
+        assert!(frame.details.error.is_some());
+
+        // The first row was decoded completely
+        let texture = frame.texture;
+        let stride = texture.width() as usize * 4;
+        let mut data = vec![0; stride * texture.height() as usize];
+        texture.download(&mut data, stride);
+        assert_eq!(data[..stride], reference[..stride]);
+    });
+}
+
+/// Truncated JPEGs return what the decoder could fill in
+#[test]
+fn partial_jpeg() {
+    async_global_executor::block_on(async {
+        let path = std::fs::read_dir("test-images/images/color")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension() == Some("jpg".as_ref()))
+            .unwrap();
+
+        // Complete files are not marked as incomplete
+        let frame = load_accept_partial(&path).await;
+        assert_ne!(frame.details.incomplete, Some(true));
+        assert!(frame.details.error.is_none());
+
+        let data = std::fs::read(&path).unwrap();
+        let path = std::env::temp_dir().join(format!("glycin-partial-{}.jpg", std::process::id()));
+        std::fs::write(&path, &data[..data.len() / 2]).unwrap();
+
+        let frame = load_accept_partial(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(frame.details.incomplete, Some(true));
+        assert!(frame.details.error.is_some());
+    });
+}
+
+async fn load_accept_partial(path: &Path) -> glycin::Frame {
+    let mut loader = glycin::Loader::new(gio::File::for_path(path));
+    loader.accept_partial(true);
+    let image = loader.load().await.unwrap();
+    image.next_frame().await.unwrap()
+}
+
+/// Time spent passing slow input to the loader doesn't count towards the timeout
+#[test]
+fn slow_stream() {
+    async_global_executor::block_on(async {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="2" height="3"><rect width="2" height="3" fill="red"/></svg>"#;
+
+        let path = std::env::temp_dir().join(format!("glycin-slow-{}.svg", std::process::id()));
+        let status = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let writer = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let mut fifo = std::fs::OpenOptions::new().write(true).open(path)?;
+                let (head, tail) = svg.split_at(svg.len() / 2);
+                fifo.write_all(head)?;
+                std::thread::sleep(Duration::from_secs(2));
+                fifo.write_all(tail)
+            }
+        });
+
+        let mut loader = glycin::Loader::new(gio::File::for_path(&path));
+        loader.timeout(Duration::from_secs(1));
+        let result = loader.load().await;
+
+        let written = writer.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let image = result.unwrap();
+        written.unwrap();
+        assert_eq!(image.info().width, 2);
+        assert_eq!(image.info().height, 3);
+    });
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 struct TestResult {
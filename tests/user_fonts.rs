@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+/// Render text with a font that is only installed for the user
+///
+/// The font `fonts/GlycinTest.ttf` maps "A" to a square that fills the em box.
+/// If the SVG loader falls back to another font, the corners of the square
+/// stay white.
+#[test]
+fn user_fonts() {
+    let mechanism = async_global_executor::block_on(glycin::SandboxMechanism::detect());
+    if matches!(mechanism, glycin::SandboxMechanism::FlatpakSpawn) {
+        eprintln!("Skipping user fonts: flatpak-spawn sandboxes don't expose user fonts");
+        return;
+    }
+
+    let dir = std::env::temp_dir().join(format!("glycin-user-fonts-{}", std::process::id()));
+    let font_dir = dir.join("data/fonts");
+    std::fs::create_dir_all(&font_dir).unwrap();
+    std::fs::copy("fonts/GlycinTest.ttf", font_dir.join("GlycinTest.ttf")).unwrap();
+
+    // Has to be set before glib caches the user dirs
+    std::env::set_var("XDG_DATA_HOME", dir.join("data"));
+    std::env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+
+    let svg = dir.join("text.svg");
+    std::fs::write(
+        &svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+  <rect width="100" height="100" fill="white"/>
+  <text x="10" y="80" font-family="Glycin Test" font-size="80" fill="black">A</text>
+</svg>"#,
+    )
+    .unwrap();
+
+    let data = async_global_executor::block_on(download(svg));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Square spans from (10, 16) to (90, 96)
+    for (x, y) in [(15, 20), (85, 20), (50, 50), (15, 90), (85, 90)] {
+        let value = data[(y * 100 + x) * 4];
+        assert!(value < 64, "Pixel ({x}, {y}) is not black: {value}");
+    }
+}
+
+async fn download(path: PathBuf) -> Vec<u8> {
+    let file = gio::File::for_path(&path);
+    let image = glycin::Loader::new(file).load().await.unwrap();
+    let texture = image.next_frame().await.unwrap().texture;
+
+    let mut data = vec![0; texture.width() as usize * texture.height() as usize * 4];
+    texture.download(&mut data, texture.width() as usize * 4);
+    data
+}
@@ -10,8 +10,16 @@ async fn run() -> Result<(), glycin::Error> {
     let bin = args.next().unwrap();
     let Some(path) = args.next() else {
         eprintln!("Usage: {bin} <IMAGE PATH> [NUMBER FRAMES]");
+        eprintln!("       {bin} --sandbox-diagnostics");
         std::process::exit(2);
     };
+
+    if path == "--sandbox-diagnostics" {
+        println!("[sandbox]");
+        println!("{}", glycin::sandbox_diagnostics().await);
+        return Ok(());
+    }
+
     let n_frames = args.next().and_then(|x| x.parse().ok()).unwrap_or(1);
 
     let file = gio::File::for_path(path);